@@ -39,3 +39,15 @@ find "$IN_DIR/a" "$IN_DIR/d" -name \*.txt > "$OUT_DIR/name_txt_path_a_d.txt"
 find "$IN_DIR" -name a* > "$OUT_DIR/name_a.txt"
 find "$IN_DIR" -type f -name a* > "$OUT_DIR/type_f_name_a.txt"
 find "$IN_DIR" -type d -name a* > "$OUT_DIR/type_d_name_a.txt"
+
+find "$IN_DIR" -name \*.csv -o -type l > "$OUT_DIR/expr_name_or_type.txt"
+find "$IN_DIR" \( -name \*.csv -o -name \*.mp3 \) -not -type l > "$OUT_DIR/expr_parens_not.txt"
+find "$IN_DIR" ! -type d -a ! -name \*.txt > "$OUT_DIR/expr_bang_and.txt"
+find "$IN_DIR" -type f -name a\* -o -type d -name a\* > "$OUT_DIR/expr_precedence.txt"
+find "$IN_DIR" -type f,l -name b\* > "$OUT_DIR/expr_type_list.txt"
+
+find "$IN_DIR" -maxdepth 1 > "$OUT_DIR/max_depth_1.txt"
+find "$IN_DIR" -mindepth 2 > "$OUT_DIR/min_depth_2.txt"
+find "$IN_DIR" -maxdepth 2 -mindepth 2 -type f > "$OUT_DIR/depth_2_type_f.txt"
+
+find "$IN_DIR" -size 2c > "$OUT_DIR/size_2c.txt"
@@ -1,16 +1,25 @@
 use crate::EntryType::*;
-use clap::{builder::PossibleValue, Arg, ArgAction, Command};
+use clap::{builder::PossibleValue, value_parser, Arg, ArgAction, Command};
+use globset::{Glob, GlobMatcher};
 use regex::Regex;
+use std::env;
 use std::error::Error;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
-use walkdir::WalkDir;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, Metadata};
+use std::io::{self, BufRead};
+use std::iter::Peekable;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::time::SystemTime;
+use walkdir::{DirEntry, WalkDir};
 
 #[derive(Debug)]
 pub struct Config {
     paths: Vec<String>,
-    entry_types: Vec<EntryType>,
-    names: Vec<String>,
+    expr: Expr,
+    max_depth: Option<usize>,
+    min_depth: Option<usize>,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -20,14 +29,356 @@ enum EntryType {
     Link,
 }
 
+impl EntryType {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        let file_type = entry.file_type();
+        match self {
+            Dir => file_type.is_dir(),
+            File => file_type.is_file(),
+            Link => file_type.is_symlink(),
+        }
+    }
+}
+
+/// A find-style expression, evaluated against each entry walked.
+#[derive(Debug)]
+enum Expr {
+    /// the empty expression, which selects everything
+    True,
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    /// -name: a glob against the file name
+    Name(GlobMatcher),
+    /// --name: a regex against the file name
+    Regex(Regex),
+    Type(EntryType),
+    /// -size: the size rounded up to a number of units of the given bytes
+    Size(Compare, u64),
+    /// -empty: an empty regular file or directory
+    Empty,
+    Perm(Perm),
+    /// -mtime, -mmin and the like: how long before `now` one of the entry's
+    /// times was
+    Age(Timestamp, Compare, AgeUnit, SystemTime),
+    /// -newer: modified later than the reference file was
+    Newer(SystemTime),
+    /// -print: list the entry; implied when there is no other action
+    Print,
+    /// -exec, -execdir and -ok
+    Exec(Exec),
+}
+
+/// A command run for entries, where {} stands for the entry.
+#[derive(Debug)]
+struct Exec {
+    command: Vec<String>,
+    /// -execdir: run from the entry's directory, on ./NAME
+    in_dir: bool,
+    /// -ok: ask on stderr first, and give the command no stdin
+    ask: bool,
+    /// for the + forms, the entries waiting to be run in one command
+    batch: Option<Batch>,
+}
+
+#[derive(Debug)]
+struct Batch {
+    /// where the entries are, for -execdir
+    dir: Option<PathBuf>,
+    paths: Vec<OsString>,
+    /// bytes the paths take of the argument list, and how many they may
+    size: usize,
+    room: usize,
+    failed: bool,
+}
+
+/// How many bytes the argument list and environment of a command may take:
+/// what xargs uses by default, well under the limit of most systems.
+#[cfg(not(windows))]
+const ARG_MAX: usize = 128 * 1024;
+/// Windows limits the whole command line to 32767 characters.
+#[cfg(windows)]
+const ARG_MAX: usize = 32 * 1024 - 1;
+
+/// A number as find's tests take it: +N is more than N, -N less than N, and
+/// N exactly N.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compare {
+    Greater(u64),
+    Less(u64),
+    Equal(u64),
+}
+
+impl Compare {
+    /// Parses +N, -N or N.
+    fn parse(arg: &str) -> Option<Self> {
+        let (make, digits): (fn(u64) -> Self, _) = match arg.as_bytes().first()? {
+            b'+' => (Compare::Greater, &arg[1..]),
+            b'-' => (Compare::Less, &arg[1..]),
+            _ => (Compare::Equal, arg),
+        };
+        // from_str would take a second sign
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok().map(make)
+    }
+
+    fn matches(self, n: u64) -> bool {
+        match self {
+            Compare::Greater(limit) => n > limit,
+            Compare::Less(limit) => n < limit,
+            Compare::Equal(limit) => n == limit,
+        }
+    }
+}
+
+/// -perm: which of the permission bits in the mode have to be set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Perm {
+    /// MODE: exactly these
+    Exact(u32),
+    /// -MODE: all of these, maybe others
+    All(u32),
+    /// /MODE: any of these, or anything if there are none
+    Any(u32),
+}
+
+impl Perm {
+    fn matches(self, mode: u32) -> bool {
+        let mode = mode & 0o7777;
+        match self {
+            Perm::Exact(bits) => mode == bits,
+            Perm::All(bits) => mode & bits == bits,
+            Perm::Any(bits) => bits == 0 || mode & bits != 0,
+        }
+    }
+}
+
+/// Which of an entry's times -Xtime and -Xmin look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Timestamp {
+    Accessed,
+    Modified,
+    Changed,
+}
+
+impl Timestamp {
+    fn of(self, metadata: &Metadata) -> Option<SystemTime> {
+        match self {
+            Timestamp::Accessed => metadata.accessed().ok(),
+            Timestamp::Modified => metadata.modified().ok(),
+            Timestamp::Changed => changed(metadata),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AgeUnit {
+    /// -Xtime: whole days, the fraction dropped
+    Days,
+    /// -Xmin: minutes, where N is anything over N - 1 up to N
+    Minutes,
+}
+
+impl AgeUnit {
+    /// Whether an age in seconds, negative for a time in the future, passes.
+    fn matches(self, age: Compare, seconds: f64) -> bool {
+        match (self, age) {
+            (AgeUnit::Days, _) => {
+                let days = (seconds / 86400.0).floor();
+                match age {
+                    Compare::Greater(n) => days > n as f64,
+                    Compare::Less(n) => days < n as f64,
+                    Compare::Equal(n) => days == n as f64,
+                }
+            }
+            // in floating point, so that no N overflows
+            (AgeUnit::Minutes, Compare::Greater(n)) => seconds > n as f64 * 60.0,
+            (AgeUnit::Minutes, Compare::Less(n)) => seconds < n as f64 * 60.0,
+            (AgeUnit::Minutes, Compare::Equal(n)) => (seconds / 60.0).ceil() == n as f64,
+        }
+    }
+}
+
+impl Exec {
+    /// Runs the command on the entry and returns whether it succeeded, or
+    /// for the + forms adds the entry to the batch and returns true.
+    fn eval(&mut self, entry: &DirEntry) -> bool {
+        let (dir, path) = if self.in_dir {
+            let dir = entry
+                .path()
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            let path = Path::new(".").join(entry.file_name());
+            (Some(dir.to_path_buf()), path.into_os_string())
+        } else {
+            (None, entry.path().as_os_str().to_os_string())
+        };
+
+        match &mut self.batch {
+            None => {
+                let args: Vec<_> = self
+                    .command
+                    .iter()
+                    .map(|arg| substitute(arg, &path))
+                    .collect();
+                if self.ask && !confirm(&args) {
+                    return false;
+                }
+                run_command(args, dir.as_deref(), self.ask)
+            }
+            Some(batch) => {
+                let size = arg_size(&path);
+                if batch.dir != dir || batch.size + size > batch.room {
+                    batch.run(&self.command);
+                    batch.dir = dir;
+                }
+                batch.size += size;
+                batch.paths.push(path);
+                true
+            }
+        }
+    }
+}
+
+impl Batch {
+    /// An empty batch for `command`, which ends in {}.
+    fn new(command: &[String]) -> Self {
+        let environment: usize = env::vars_os()
+            .map(|(key, value)| arg_size(&key) + value.len() + 1)
+            .sum();
+        let fixed: usize = command[..command.len() - 1]
+            .iter()
+            .map(|arg| arg_size(arg.as_ref()))
+            .sum();
+        Batch {
+            dir: None,
+            paths: vec![],
+            size: 0,
+            // leaving headroom as xargs does
+            room: ARG_MAX.saturating_sub(environment + fixed + 2048),
+            failed: false,
+        }
+    }
+
+    /// Runs `command` on the paths waiting, if there are any.
+    fn run(&mut self, command: &[String]) {
+        if self.paths.is_empty() {
+            return;
+        }
+        let fixed = command[..command.len() - 1].iter().map(OsString::from);
+        let args = fixed.chain(self.paths.drain(..)).collect();
+        if !run_command(args, self.dir.as_deref(), false) {
+            self.failed = true;
+        }
+        self.size = 0;
+    }
+}
+
+impl Expr {
+    /// Whether `entry` is selected; -a and -o stop at the first operand that
+    /// decides the result.
+    fn eval(&mut self, entry: &DirEntry) -> bool {
+        match self {
+            Expr::True => true,
+            Expr::Not(expr) => !expr.eval(entry),
+            Expr::And(left, right) => left.eval(entry) && right.eval(entry),
+            Expr::Or(left, right) => left.eval(entry) || right.eval(entry),
+            Expr::Name(glob) => glob.is_match(entry.file_name()),
+            Expr::Regex(re) => re.is_match(&entry.file_name().to_string_lossy()),
+            Expr::Type(entry_type) => entry_type.matches(entry),
+            Expr::Size(size, unit) => entry
+                .metadata()
+                .is_ok_and(|metadata| size.matches(metadata.len().div_ceil(*unit))),
+            Expr::Empty => is_empty(entry),
+            Expr::Perm(perm) => entry
+                .metadata()
+                .is_ok_and(|metadata| perm.matches(mode(&metadata))),
+            Expr::Age(timestamp, age, unit, now) => entry
+                .metadata()
+                .ok()
+                .and_then(|metadata| timestamp.of(&metadata))
+                .is_some_and(|time| unit.matches(*age, seconds_between(time, *now))),
+            Expr::Newer(reference) => entry
+                .metadata()
+                .ok()
+                .and_then(|metadata| metadata.modified().ok())
+                .is_some_and(|time| time > *reference),
+            Expr::Print => {
+                println!("{}", entry.path().to_string_lossy());
+                true
+            }
+            Expr::Exec(exec) => exec.eval(entry),
+        }
+    }
+
+    /// Whether the expression does anything but select entries, which
+    /// otherwise are printed.
+    fn has_action(&self) -> bool {
+        match self {
+            Expr::Not(expr) => expr.has_action(),
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.has_action() || right.has_action()
+            }
+            Expr::Print | Expr::Exec(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Runs the commands still waiting in batches, returning whether every
+    /// batched command succeeded.
+    fn finish(&mut self) -> bool {
+        match self {
+            Expr::Not(expr) => expr.finish(),
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                // both sides, however the first turns out
+                left.finish() & right.finish()
+            }
+            Expr::Exec(Exec {
+                command,
+                batch: Some(batch),
+                ..
+            }) => {
+                batch.run(command);
+                !batch.failed
+            }
+            _ => true,
+        }
+    }
+
+    fn and(self, right: Expr) -> Expr {
+        match self {
+            Expr::True => right,
+            left => Expr::And(Box::new(left), Box::new(right)),
+        }
+    }
+
+    fn or(self, right: Expr) -> Expr {
+        Expr::Or(Box::new(self), Box::new(right))
+    }
+}
+
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
 pub fn get_args() -> MyResult<Config> {
+    // clap knows nothing of find's expressions, so they are taken out first
+    let (args, expression) = split_expression(env::args().collect());
+
     let matches = Command::new("findr")
         .bin_name("findr")
         .version("0.1.0")
         .author("Kanahiro Iguchi")
         .about("hogehoge")
+        .after_help(
+            "EXPRESSION may follow the options: -name GLOB, -type d|f|l, \
+             -size [+-]N[cwbkMG], -empty, -perm [-/]MODE, \
+             -mtime/-atime/-ctime [+-]DAYS, -mmin/-amin/-cmin [+-]MINUTES, \
+             -newer FILE, -print, -exec CMD ;, -exec CMD {} +, \
+             -execdir CMD ;, -execdir CMD {} +, -ok CMD ;, \
+             ! EXPR, -not EXPR, EXPR -a EXPR, EXPR -o EXPR and ( EXPR ).",
+        )
         .args([
             Arg::new("paths")
                 .value_name("PATH")
@@ -48,85 +399,465 @@ pub fn get_args() -> MyResult<Config> {
                 .short('n')
                 .action(ArgAction::Append)
                 .num_args(1),
+            Arg::new("max-depth")
+                .value_name("N")
+                .long("max-depth")
+                .help("descend at most N levels below the paths")
+                .value_parser(value_parser!(usize)),
+            Arg::new("min-depth")
+                .value_name("N")
+                .long("min-depth")
+                .help("list nothing less than N levels below the paths")
+                .value_parser(value_parser!(usize)),
         ])
-        .get_matches();
+        .get_matches_from(args);
+
+    // --name and --type each select entries matching any of their values
+    let mut expr = Expr::True;
+    if let Some(names) = matches.get_many::<String>("name") {
+        let names = names
+            .map(|name| {
+                Regex::new(name)
+                    .map(Expr::Regex)
+                    .map_err(|_| format!("invalid value '{}'", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(names) = names.into_iter().reduce(Expr::or) {
+            expr = expr.and(names);
+        }
+    }
+    if let Some(types) = matches.get_many::<String>("type") {
+        if let Some(types) = types.map(|t| Expr::Type(entry_type(t))).reduce(Expr::or) {
+            expr = expr.and(types);
+        }
+    }
+    if !expression.is_empty() {
+        expr = expr.and(parse_expression(&expression)?);
+    }
+    if !expr.has_action() {
+        expr = expr.and(Expr::Print);
+    }
 
     Ok(Config {
         paths: match matches.get_many::<String>("paths") {
             Some(paths) => paths.map(|s| s.to_string()).collect(),
             None => vec![],
         },
-        entry_types: match matches.get_many::<String>("type") {
-            Some(types) => types
-                .map(|s| match s.as_str() {
-                    "d" => Dir,
-                    "f" => File,
-                    "l" => Link,
-                    _ => unreachable!(),
-                })
-                .collect(),
-            None => vec![],
-        },
-        names: match matches.get_many::<String>("name") {
-            Some(names) => names.map(|s| s.to_string()).collect(),
-            None => vec![],
-        },
+        expr,
+        max_depth: matches.get_one::<usize>("max-depth").copied(),
+        min_depth: matches.get_one::<usize>("min-depth").copied(),
     })
 }
 
-pub fn run(cfg: Config) -> MyResult<()> {
-    let regexes = cfg
-        .names
-        .iter()
-        .map(|name| match Regex::new(&format!("{}", name)) {
-            Ok(re) => re,
-            Err(_) => {
-                eprintln!("error: invalid value '{}'", name);
-                std::process::exit(1);
+/// How many arguments follow an expression token, or `None` if `arg` is not
+/// part of an expression.
+fn expression_arity(arg: &str) -> Option<usize> {
+    match arg {
+        "(" | ")" | "!" | "-not" | "-a" | "-and" | "-o" | "-or" => Some(0),
+        "-empty" | "-print" => Some(0),
+        "-name" | "-type" | "-size" | "-perm" => Some(1),
+        "-mtime" | "-mmin" | "-atime" | "-amin" | "-ctime" | "-cmin" | "-newer" => Some(1),
+        _ => None,
+    }
+}
+
+/// Whether `arg` starts a command, which runs to a ; or to a {} +.
+fn is_command(arg: &str) -> bool {
+    matches!(arg, "-exec" | "-execdir" | "-ok")
+}
+
+/// Whether `arg` ends a command whose previous argument was `last`.
+fn ends_command(arg: &str, last: &str) -> bool {
+    arg == ";" || arg == "+" && last == "{}"
+}
+
+/// The option that find spells as `arg`, as in -maxdepth for --max-depth.
+fn find_option(arg: &str) -> Option<&'static str> {
+    match arg {
+        "-maxdepth" => Some("--max-depth"),
+        "-mindepth" => Some("--min-depth"),
+        _ => None,
+    }
+}
+
+/// Separates the expression tokens, with their arguments, from the paths and
+/// options.
+fn split_expression(args: Vec<String>) -> (Vec<String>, Vec<String>) {
+    let mut rest = vec![];
+    let mut expression: Vec<String> = vec![];
+    let mut operands = 0;
+    let mut in_command = false;
+    for (i, arg) in args.into_iter().enumerate() {
+        if in_command {
+            in_command = !ends_command(&arg, expression.last().unwrap());
+            expression.push(arg);
+        } else if operands > 0 {
+            operands -= 1;
+            expression.push(arg);
+        } else if let Some(option) = find_option(&arg) {
+            rest.push(option.to_string());
+        } else if is_command(&arg) && i > 0 {
+            in_command = true;
+            expression.push(arg);
+        } else if let Some(arity) = expression_arity(&arg).filter(|_| i > 0) {
+            operands = arity;
+            expression.push(arg);
+        } else {
+            rest.push(arg);
+        }
+    }
+    (rest, expression)
+}
+
+fn entry_type(name: &str) -> EntryType {
+    match name {
+        "d" => Dir,
+        "f" => File,
+        "l" => Link,
+        _ => unreachable!(),
+    }
+}
+
+/// Parses a whole expression: -o binds loosest, then -a (also implied by two
+/// adjacent terms), then ! and -not.
+fn parse_expression(tokens: &[String]) -> MyResult<Expr> {
+    let mut tokens = tokens.iter().map(String::as_str).peekable();
+    let expr = parse_or(&mut tokens)?;
+    match tokens.next() {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected '{}' in expression", token).into()),
+    }
+}
+
+fn parse_or<'a, I: Iterator<Item = &'a str>>(tokens: &mut Peekable<I>) -> MyResult<Expr> {
+    let mut expr = parse_and(tokens)?;
+    while let Some(&op @ ("-o" | "-or")) = tokens.peek() {
+        tokens.next();
+        expr = expr.or(parse_and(tokens).map_err(|e| missing_operand(op, e))?);
+    }
+    Ok(expr)
+}
+
+fn parse_and<'a, I: Iterator<Item = &'a str>>(tokens: &mut Peekable<I>) -> MyResult<Expr> {
+    let mut expr = parse_not(tokens)?;
+    loop {
+        match tokens.peek() {
+            Some(&op @ ("-a" | "-and")) => {
+                tokens.next();
+                expr = expr.and(parse_not(tokens).map_err(|e| missing_operand(op, e))?);
+            }
+            // any other term that follows is joined with an implied -a
+            Some(&token) if !matches!(token, "-o" | "-or" | ")") => {
+                expr = expr.and(parse_not(tokens)?);
+            }
+            _ => return Ok(expr),
+        }
+    }
+}
+
+fn parse_not<'a, I: Iterator<Item = &'a str>>(tokens: &mut Peekable<I>) -> MyResult<Expr> {
+    match tokens.peek() {
+        Some(&op @ ("!" | "-not")) => {
+            tokens.next();
+            let expr = parse_not(tokens).map_err(|e| missing_operand(op, e))?;
+            Ok(Expr::Not(Box::new(expr)))
+        }
+        _ => parse_primary(tokens),
+    }
+}
+
+fn parse_primary<'a, I: Iterator<Item = &'a str>>(tokens: &mut Peekable<I>) -> MyResult<Expr> {
+    let token = tokens.next().ok_or("expected an expression")?;
+    match token {
+        "(" => {
+            let expr = parse_or(tokens)?;
+            match tokens.next() {
+                Some(")") => Ok(expr),
+                _ => Err("missing ')' in expression".into()),
+            }
+        }
+        "-name" => {
+            let glob = argument(tokens, token)?;
+            let glob = Glob::new(glob).map_err(|_| invalid(glob, token))?;
+            Ok(Expr::Name(glob.compile_matcher()))
+        }
+        "-type" => {
+            let types = argument(tokens, token)?;
+            // several types may be given at once, as in -type f,l
+            types
+                .split(',')
+                .map(|t| match t {
+                    "d" | "f" | "l" => Ok(Expr::Type(entry_type(t))),
+                    _ => Err(invalid(t, token)),
+                })
+                .reduce(|left, right| Ok(left?.or(right?)))
+                .unwrap()
+        }
+        "-size" => {
+            let size = argument(tokens, token)?;
+            // 512-byte blocks unless a unit is given
+            let (number, unit) = match size.as_bytes().last() {
+                Some(b'c') => (&size[..size.len() - 1], 1),
+                Some(b'w') => (&size[..size.len() - 1], 2),
+                Some(b'b') => (&size[..size.len() - 1], 512),
+                Some(b'k') => (&size[..size.len() - 1], 1 << 10),
+                Some(b'M') => (&size[..size.len() - 1], 1 << 20),
+                Some(b'G') => (&size[..size.len() - 1], 1 << 30),
+                _ => (size, 512),
+            };
+            let number = Compare::parse(number).ok_or_else(|| invalid(size, token))?;
+            Ok(Expr::Size(number, unit))
+        }
+        "-empty" => Ok(Expr::Empty),
+        "-perm" => {
+            let mode = argument(tokens, token)?;
+            let perm = match mode.as_bytes().first() {
+                Some(b'-') => parse_mode(&mode[1..]).map(Perm::All),
+                Some(b'/') => parse_mode(&mode[1..]).map(Perm::Any),
+                _ => parse_mode(mode).map(Perm::Exact),
+            };
+            Ok(Expr::Perm(perm.ok_or_else(|| invalid(mode, token))?))
+        }
+        "-mtime" | "-mmin" | "-atime" | "-amin" | "-ctime" | "-cmin" => {
+            let timestamp = match &token[1..2] {
+                "a" => Timestamp::Accessed,
+                "c" => Timestamp::Changed,
+                _ => Timestamp::Modified,
+            };
+            let unit = match &token[2..] {
+                "min" => AgeUnit::Minutes,
+                _ => AgeUnit::Days,
+            };
+            let age = argument(tokens, token)?;
+            let age = Compare::parse(age).ok_or_else(|| invalid(age, token))?;
+            Ok(Expr::Age(timestamp, age, unit, SystemTime::now()))
+        }
+        "-newer" => {
+            let file = argument(tokens, token)?;
+            let modified = fs::metadata(file)
+                .and_then(|metadata| metadata.modified())
+                .map_err(|e| format!("{}: {}", file, e))?;
+            Ok(Expr::Newer(modified))
+        }
+        "-print" => Ok(Expr::Print),
+        "-exec" | "-execdir" | "-ok" => {
+            let mut command: Vec<String> = vec![];
+            let batched = loop {
+                let arg = argument(tokens, token)?;
+                if ends_command(arg, command.last().map_or("", String::as_str)) {
+                    break arg == "+";
+                }
+                command.push(arg.to_string());
+            };
+            if command.is_empty() || batched && command.len() == 1 {
+                return Err(format!("missing argument to '{}'", token).into());
+            }
+            if batched {
+                if token == "-ok" {
+                    return Err("'-ok' does not support '+'".into());
+                }
+                if command[..command.len() - 1]
+                    .iter()
+                    .any(|arg| arg.contains("{}"))
+                {
+                    return Err(
+                        format!("only one '{{}}' is supported with '{} ... +'", token).into(),
+                    );
+                }
             }
-        })
-        .collect::<Vec<_>>();
+            Ok(Expr::Exec(Exec {
+                batch: batched.then(|| Batch::new(&command)),
+                command,
+                in_dir: token == "-execdir",
+                ask: token == "-ok",
+            }))
+        }
+        _ => Err(format!("expected an expression, found '{}'", token).into()),
+    }
+}
 
-    for path in cfg.paths {
-        for entry in WalkDir::new(path) {
+/// The argument of `primary`.
+fn argument<'a, I: Iterator<Item = &'a str>>(
+    tokens: &mut Peekable<I>,
+    primary: &str,
+) -> MyResult<&'a str> {
+    tokens
+        .next()
+        .ok_or_else(|| format!("missing argument to '{}'", primary).into())
+}
+
+fn invalid(value: &str, primary: &str) -> Box<dyn Error> {
+    format!("invalid value '{}' for '{}'", value, primary).into()
+}
+
+/// Parses the permission bits of an octal mode like 644, or of a symbolic one
+/// like u+w,go=rx that starts from no bits at all.
+fn parse_mode(mode: &str) -> Option<u32> {
+    if !mode.is_empty() && mode.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return u32::from_str_radix(mode, 8)
+            .ok()
+            .filter(|&bits| bits <= 0o7777);
+    }
+
+    let mut bits = 0;
+    for clause in mode.split(',') {
+        let op = clause.find(['+', '-', '='])?;
+        let (who, perms) = clause.split_at(op);
+        let mut who_bits = 0;
+        for c in who.chars() {
+            who_bits |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return None,
+            };
+        }
+        // no one named means everyone
+        if who_bits == 0 {
+            who_bits = 0o7777;
+        }
+        let mut perm_bits = 0;
+        for c in perms[1..].chars() {
+            perm_bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                's' => 0o6000,
+                't' => 0o1000,
+                _ => return None,
+            };
+        }
+        match &perms[..1] {
+            "+" => bits |= who_bits & perm_bits,
+            "-" => bits &= !(who_bits & perm_bits),
+            _ => bits = (bits & !who_bits) | (who_bits & perm_bits),
+        }
+    }
+    Some(bits)
+}
+
+/// Whether the entry is an empty regular file or an empty directory.
+fn is_empty(entry: &DirEntry) -> bool {
+    let file_type = entry.file_type();
+    if file_type.is_dir() {
+        fs::read_dir(entry.path()).is_ok_and(|mut entries| entries.next().is_none())
+    } else {
+        file_type.is_file() && entry.metadata().is_ok_and(|metadata| metadata.len() == 0)
+    }
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+/// Without Unix modes, everything is readable and, unless read-only, writable.
+#[cfg(not(unix))]
+fn mode(metadata: &Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o666
+    }
+}
+
+/// How many seconds `time` was before `now`.
+fn seconds_between(time: SystemTime, now: SystemTime) -> f64 {
+    match now.duration_since(time) {
+        Ok(age) => age.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
+
+#[cfg(unix)]
+fn changed(metadata: &Metadata) -> Option<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+    use std::time::{Duration, UNIX_EPOCH};
+    let seconds = u64::try_from(metadata.ctime()).ok()?;
+    let nanos = u32::try_from(metadata.ctime_nsec()).ok()?;
+    UNIX_EPOCH.checked_add(Duration::new(seconds, nanos))
+}
+
+/// Without a status change time, the last modification stands in for it.
+#[cfg(not(unix))]
+fn changed(metadata: &Metadata) -> Option<SystemTime> {
+    metadata.modified().ok()
+}
+
+/// `arg` with each {} in it replaced by `path`.
+fn substitute(arg: &str, path: &OsStr) -> OsString {
+    let mut substituted = OsString::new();
+    for (i, part) in arg.split("{}").enumerate() {
+        if i > 0 {
+            substituted.push(path);
+        }
+        substituted.push(part);
+    }
+    substituted
+}
+
+/// What an argument takes of the argument list: itself, its terminating
+/// NUL and the pointer to it.
+fn arg_size(arg: &OsStr) -> usize {
+    arg.len() + 1 + mem::size_of::<usize>()
+}
+
+/// Asks on stderr whether to run the command, as -ok does.
+fn confirm(args: &[OsString]) -> bool {
+    let args: Vec<_> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+    eprint!("< {} > ? ", args.join(" "));
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).is_ok() && answer.trim_start().starts_with(['y', 'Y'])
+}
+
+/// Runs the command `args` in `dir`, if given, and returns whether it exited
+/// successfully.
+fn run_command(args: Vec<OsString>, dir: Option<&Path>, no_stdin: bool) -> bool {
+    let mut command = process::Command::new(&args[0]);
+    command.args(&args[1..]);
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    if no_stdin {
+        command.stdin(Stdio::null());
+    }
+    match command.status() {
+        Ok(status) => status.success(),
+        Err(e) => {
+            eprintln!("{}: {}", args[0].to_string_lossy(), e);
+            false
+        }
+    }
+}
+
+/// Adds which operator lacked an operand to a parse error.
+fn missing_operand(op: &str, e: Box<dyn Error>) -> Box<dyn Error> {
+    format!("{} after '{}'", e, op).into()
+}
+
+pub fn run(mut cfg: Config) -> MyResult<()> {
+    for path in &cfg.paths {
+        let mut walk = WalkDir::new(path);
+        if let Some(depth) = cfg.max_depth {
+            walk = walk.max_depth(depth);
+        }
+        if let Some(depth) = cfg.min_depth {
+            walk = walk.min_depth(depth);
+        }
+        for entry in walk {
             match entry {
                 Err(e) => eprintln!("error: {}", e),
                 Ok(entry) => {
-                    let path = entry.path().to_string_lossy();
-
-                    if cfg.names.len() > 0 {
-                        if !regexes
-                            .iter()
-                            .any(|re| re.is_match(&entry.file_name().to_string_lossy()))
-                        {
-                            // 正規表現に合致しなければスキップ
-                            continue;
-                        }
-                    }
-
-                    if cfg.entry_types.len() > 0 {
-                        if entry.file_type().is_dir() && cfg.entry_types.contains(&Dir) {
-                            println!("{}", path)
-                        } else if entry.file_type().is_file() && cfg.entry_types.contains(&File) {
-                            println!("{}", path)
-                        } else if entry.file_type().is_symlink() && cfg.entry_types.contains(&Link)
-                        {
-                            println!("{}", path)
-                        };
-                    } else {
-                        println!("{}", path)
-                    }
+                    cfg.expr.eval(&entry);
                 }
             }
         }
     }
 
-    Ok(())
-}
-
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+    if !cfg.expr.finish() {
+        return Err("a command run with '+' failed".into());
     }
+    Ok(())
 }
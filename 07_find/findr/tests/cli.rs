@@ -60,14 +60,14 @@ fn dies_bad_type() -> Result<()> {
 
 // --------------------------------------------------
 #[cfg(windows)]
-fn format_file_name(expected_file: &str) -> Cow<str> {
+fn format_file_name(expected_file: &str) -> Cow<'_, str> {
     // Equivalent to: Cow::Owned(format!("{}.windows", expected_file))
     format!("{}.windows", expected_file).into()
 }
 
 // --------------------------------------------------
 #[cfg(not(windows))]
-fn format_file_name(expected_file: &str) -> Cow<str> {
+fn format_file_name(expected_file: &str) -> Cow<'_, str> {
     // Equivalent to: Cow::Borrowed(expected_file)
     expected_file.into()
 }
@@ -312,3 +312,422 @@ fn unreadable_dir() -> Result<()> {
     assert!(stderr.contains("cant-touch-this: Permission denied"));
     Ok(())
 }
+
+// --------------------------------------------------
+#[test]
+fn expr_name_or_type() -> Result<()> {
+    run(
+        &["tests/inputs", "-name", "*.csv", "-o", "-type", "l"],
+        "tests/expected/expr_name_or_type.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_parens_not() -> Result<()> {
+    run(
+        &[
+            "tests/inputs",
+            "(",
+            "-name",
+            "*.csv",
+            "-or",
+            "-name",
+            "*.mp3",
+            ")",
+            "-not",
+            "-type",
+            "l",
+        ],
+        "tests/expected/expr_parens_not.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_bang_and() -> Result<()> {
+    run(
+        &["tests/inputs", "!", "-type", "d", "-a", "!", "-name", "*.txt"],
+        "tests/expected/expr_bang_and.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_precedence() -> Result<()> {
+    run(
+        &[
+            "tests/inputs",
+            "-type",
+            "f",
+            "-name",
+            "a*",
+            "-o",
+            "-type",
+            "d",
+            "-and",
+            "-name",
+            "a*",
+        ],
+        "tests/expected/expr_precedence.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_type_list() -> Result<()> {
+    run(
+        &["tests/inputs", "-type", "f,l", "-name", "b*"],
+        "tests/expected/expr_type_list.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_with_options() -> Result<()> {
+    run(
+        &["tests/inputs", "-t", "f", "l", "-not", "-name", "*.txt"],
+        "tests/expected/expr_bang_and.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_expression() -> Result<()> {
+    for (args, expected) in [
+        (vec!["-o", "-name", "a"], "expected an expression, found '-o'"),
+        (vec!["(", "-name", "a"], "missing ')'"),
+        (vec!["-name", "a", ")"], "unexpected ')'"),
+        (vec!["-not"], "expected an expression after '-not'"),
+        (vec!["-type", "x"], "invalid value 'x' for '-type'"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .arg("tests/inputs")
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(expected));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_depth() -> Result<()> {
+    run(
+        &["tests/inputs", "--max-depth", "1"],
+        "tests/expected/max_depth_1.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn min_depth() -> Result<()> {
+    run(
+        &["tests/inputs", "--min-depth", "2"],
+        "tests/expected/min_depth_2.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn min_max_depth_find_style() -> Result<()> {
+    run(
+        &["tests/inputs", "-maxdepth", "2", "-mindepth", "2", "-type", "f"],
+        "tests/expected/depth_2_type_f.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_depth() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "--max-depth", "one"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--max-depth"));
+    Ok(())
+}
+
+// --------------------------------------------------
+/// The entries findr lists under `dir`, relative to it and sorted.
+fn find_in(dir: &Path, args: &[&str]) -> Result<Vec<String>> {
+    let output = Command::cargo_bin(PRG)?
+        .current_dir(dir)
+        .arg(".")
+        .args(args)
+        .output()?;
+    assert!(output.status.success());
+    let mut lines: Vec<String> = String::from_utf8(output.stdout)?
+        .lines()
+        .map(|line| line.to_string())
+        .collect();
+    lines.sort();
+    Ok(lines)
+}
+
+// --------------------------------------------------
+#[test]
+fn size_2c() -> Result<()> {
+    run(&["tests/inputs", "-size", "2c"], "tests/expected/size_2c.txt")
+}
+
+// --------------------------------------------------
+#[test]
+fn size_rounds_up() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("zero"), "")?;
+    fs::write(dir.path().join("one"), [0; 1])?;
+    fs::write(dir.path().join("block"), [0; 512])?;
+    fs::write(dir.path().join("block+1"), [0; 513])?;
+    fs::write(dir.path().join("mega+1"), vec![0; (1 << 20) + 1])?;
+
+    let sizes = |size| find_in(dir.path(), &["-type", "f", "-size", size]);
+    assert_eq!(sizes("1")?, ["./block", "./one"]);
+    assert_eq!(sizes("-1")?, ["./zero"]);
+    assert_eq!(sizes("+1")?, ["./block+1", "./mega+1"]);
+    assert_eq!(sizes("1k")?, ["./block", "./block+1", "./one"]);
+    assert_eq!(sizes("2M")?, ["./mega+1"]);
+    // rounding up leaves nothing but empty files below 1M
+    assert_eq!(sizes("-1M")?, ["./zero"]);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn empty() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir_all(dir.path().join("empty-dir"))?;
+    fs::create_dir_all(dir.path().join("full-dir"))?;
+    fs::write(dir.path().join("full-dir/empty-file"), "")?;
+    fs::write(dir.path().join("full-file"), "x")?;
+
+    assert_eq!(
+        find_in(dir.path(), &["-empty"])?,
+        ["./empty-dir", "./full-dir/empty-file"]
+    );
+    assert_eq!(
+        find_in(dir.path(), &["-not", "-empty", "-name", "full*"])?,
+        ["./full-dir", "./full-file"]
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn perm() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir()?;
+    for (name, mode) in [("644", 0o644), ("755", 0o755), ("600", 0o600), ("4755", 0o4755)] {
+        let path = dir.path().join(name);
+        fs::write(&path, "")?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+    }
+
+    let perms = |mode| find_in(dir.path(), &["-type", "f", "-perm", mode]);
+    assert_eq!(perms("644")?, ["./644"]);
+    assert_eq!(perms("-644")?, ["./4755", "./644", "./755"]);
+    assert_eq!(perms("/011")?, ["./4755", "./755"]);
+    assert_eq!(perms("/o+r")?, ["./4755", "./644", "./755"]);
+    assert_eq!(perms("-u+s")?, ["./4755"]);
+    assert_eq!(perms("u=rw,go=r")?, ["./644"]);
+    assert_eq!(perms("-a+x")?, ["./4755", "./755"]);
+    assert_eq!(perms("/0")?.len(), 4);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_size_or_perm() -> Result<()> {
+    for (args, expected) in [
+        (["-size", "1x"], "invalid value '1x' for '-size'"),
+        (["-size", "+-1"], "invalid value '+-1' for '-size'"),
+        (["-perm", "8"], "invalid value '8' for '-perm'"),
+        (["-perm", "u+q"], "invalid value 'u+q' for '-perm'"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .arg("tests/inputs")
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(expected));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn times() -> Result<()> {
+    use filetime::{set_file_atime, set_file_mtime, FileTime};
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempfile::tempdir()?;
+    let now = SystemTime::now();
+    let ago = |secs| FileTime::from_system_time(now - Duration::from_secs(secs));
+    for (name, mtime, atime) in [
+        ("30s", 30, 10 * 86400),
+        ("90s", 90, 50 * 3600),
+        ("50h", 50 * 3600, 90),
+        ("10d", 10 * 86400, 30),
+    ] {
+        let path = dir.path().join(name);
+        fs::write(&path, "")?;
+        set_file_mtime(&path, ago(mtime))?;
+        set_file_atime(&path, ago(atime))?;
+    }
+
+    let find = |args: &[&str]| find_in(dir.path(), &[&["-type", "f"], args].concat());
+    assert_eq!(find(&["-mmin", "1"])?, ["./30s"]);
+    assert_eq!(find(&["-mmin", "2"])?, ["./90s"]);
+    assert_eq!(find(&["-mmin", "-2"])?, ["./30s", "./90s"]);
+    assert_eq!(find(&["-mmin", "+1"])?, ["./10d", "./50h", "./90s"]);
+    assert_eq!(find(&["-mmin", "+400000000000000000"])?, Vec::<String>::new());
+    assert_eq!(find(&["-mmin", "-400000000000000000"])?.len(), 4);
+    assert_eq!(find(&["-mtime", "0"])?, ["./30s", "./90s"]);
+    assert_eq!(find(&["-mtime", "2"])?, ["./50h"]);
+    assert_eq!(find(&["-mtime", "+0"])?, ["./10d", "./50h"]);
+    assert_eq!(find(&["-mtime", "-3"])?, ["./30s", "./50h", "./90s"]);
+    assert_eq!(find(&["-amin", "-1"])?, ["./10d"]);
+    assert_eq!(find(&["-atime", "+2"])?, ["./30s"]);
+    assert_eq!(find(&["-atime", "2"])?, ["./90s"]);
+    // the status change times are all from just now
+    assert_eq!(find(&["-cmin", "-1"])?.len(), 4);
+    assert_eq!(find(&["-ctime", "+0"])?, Vec::<String>::new());
+
+    assert_eq!(find(&["-newer", "50h"])?, ["./30s", "./90s"]);
+    assert_eq!(find(&["-not", "-newer", "90s"])?, ["./10d", "./50h", "./90s"]);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_time() -> Result<()> {
+    let bad = gen_bad_file();
+    for (args, expected) in [
+        (["-mtime", "1d"], "invalid value '1d' for '-mtime'".to_string()),
+        (["-cmin", "--1"], "invalid value '--1' for '-cmin'".to_string()),
+        (["-newer", &bad], format!("{}: ", bad)),
+    ] {
+        Command::cargo_bin(PRG)?
+            .arg("tests/inputs")
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(expected));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn exec() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir(dir.path().join("sub"))?;
+    fs::write(dir.path().join("a"), "a")?;
+    fs::write(dir.path().join("sub/b"), "")?;
+
+    assert_eq!(
+        find_in(dir.path(), &["-type", "f", "-exec", "echo", "found", "{}", ";"])?,
+        ["found ./a", "found ./sub/b"]
+    );
+    // {} is replaced within arguments too, and -exec is true when the
+    // command succeeds
+    assert_eq!(
+        find_in(
+            dir.path(),
+            &["-type", "f", "-exec", "test", "-s", "{}", ";", "-exec", "echo", "<{}>", ";"]
+        )?,
+        ["<./a>"]
+    );
+    // an action stops entries being printed but -print still does
+    assert_eq!(
+        find_in(dir.path(), &["-exec", "test", "-d", "{}", ";", "-print"])?,
+        [".", "./sub"]
+    );
+
+    let name = dir.path().file_name().unwrap().to_string_lossy();
+    let here = ["sh", "-c", "echo $(basename \"$PWD\") \"$@\"", "sh", "{}"];
+    assert_eq!(
+        find_in(dir.path(), &[&["-type", "f", "-execdir"], &here[..], &[";"]].concat())?,
+        [format!("{} ./a", name), "sub ./b".to_string()]
+    );
+    assert_eq!(
+        find_in(dir.path(), &[&["-type", "f", "-execdir"], &here[..], &["+"]].concat())?,
+        [format!("{} ./a", name), "sub ./b".to_string()]
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn exec_batches() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let long = "x".repeat(100);
+    for i in 0..2000 {
+        fs::write(dir.path().join(format!("{}{:04}", long, i)), "")?;
+    }
+
+    // more than fits in one argument list
+    let counts = find_in(
+        dir.path(),
+        &["-type", "f", "-exec", "sh", "-c", "echo $#", "sh", "{}", "+"],
+    )?;
+    assert!(counts.len() > 1);
+    let total: usize = counts.iter().map(|count| count.parse::<usize>().unwrap()).sum();
+    assert_eq!(total, 2000);
+
+    Command::cargo_bin(PRG)?
+        .arg(dir.path())
+        .args(["-exec", "false", "{}", "+"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("a command run with '+' failed"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn ok() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("a"), "")?;
+
+    for (answer, expected) in [("n\n", ""), ("yes\n", "ran ./a\n"), ("", "")] {
+        Command::cargo_bin(PRG)?
+            .current_dir(dir.path())
+            .args([".", "-type", "f", "-ok", "echo", "ran", "{}", ";"])
+            .write_stdin(answer)
+            .assert()
+            .success()
+            .stdout(expected)
+            .stderr("< echo ran ./a > ? ");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_exec() -> Result<()> {
+    for (args, expected) in [
+        (&["-exec", "echo", "{}"][..], "missing argument to '-exec'"),
+        (&["-execdir", ";"], "missing argument to '-execdir'"),
+        (&["-exec", "{}", "+"], "missing argument to '-exec'"),
+        (&["-ok", "echo", "{}", "+"], "'-ok' does not support '+'"),
+        (
+            &["-exec", "echo", "{}", "{}", "+"],
+            "only one '{}' is supported with '-exec ... +'",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .arg("tests/inputs")
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(expected));
+    }
+    Ok(())
+}
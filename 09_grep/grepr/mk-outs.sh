@@ -47,3 +47,53 @@ grep -cri the tests/inputs > "$OUT_DIR/the.recursive.insensitive.count"
 
 # STDIN, insensitive, count
 cat tests/inputs/*.txt | grep -ci the - > "$OUT_DIR/the.recursive.insensitive.count.stdin"
+
+# Context
+grep -A1 The tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.the.capitalized.after1"
+grep -B1 up tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.up.before1"
+grep -C2 -v e tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.e.invert.context2"
+grep -A2 -B1 -i the $DIR/bustle.txt $DIR/fox.txt > "$OUT_DIR/bustle.fox.the.lowercase.insensitive.after2.before1"
+
+# Line numbers and byte offsets
+grep -n The tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.the.capitalized.n"
+grep -b up tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.up.b"
+grep -nb -v -i the tests/inputs/nobody.txt > "$OUT_DIR/nobody.txt.the.invert.insensitive.n.b"
+grep -nb -C1 the $DIR/bustle.txt $DIR/fox.txt > "$OUT_DIR/bustle.fox.the.lowercase.n.b.context1"
+
+# Files with and without matches
+grep -l The $DIR/*.txt > "$OUT_DIR/all.the.capitalized.l"
+grep -L The $DIR/*.txt > "$OUT_DIR/all.the.capitalized.L"
+grep -rL -i nobody tests/inputs > "$OUT_DIR/nobody.recursive.insensitive.L"
+
+# Only matching, color
+grep -o -i the tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.the.insensitive.o"
+grep -ob the $DIR/bustle.txt $DIR/fox.txt > "$OUT_DIR/bustle.fox.the.o.b"
+grep --color=always -n -C1 -i the $DIR/bustle.txt $DIR/fox.txt > "$OUT_DIR/bustle.fox.the.insensitive.n.context1.color"
+grep --color=always -v -C1 up tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.up.invert.context1.color"
+GREP_COLORS='mt=01;32:fn=:se=33' grep --color=always -n fox $DIR/fox.txt $DIR/bustle.txt > "$OUT_DIR/fox.n.color.grep_colors"
+
+# Multiple and fixed patterns
+grep -e fox -e bustle $DIR/*.txt > "$OUT_DIR/all.fox.bustle.e"
+printf 'fox\nThe\n' | grep -f - $DIR/bustle.txt $DIR/fox.txt > "$OUT_DIR/bustle.fox.the.fox.f"
+grep -F -o . tests/inputs/fox.txt > "$OUT_DIR/fox.txt.dot.F.o"
+grep -F -i -o THE tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.the.F.insensitive.o"
+
+# Word and line matching
+grep -w the tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.the.w"
+grep -wo -i the tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.the.w.o.insensitive"
+grep -wv -i the tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.the.w.invert.insensitive"
+grep -wo -n -e 'Nobody!' -e 'Frog—' -e '!' tests/inputs/nobody.txt > "$OUT_DIR/nobody.txt.punctuation.w.o.n"
+grep -x -i 'the bustle in a house' tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.x.insensitive"
+
+# Include and exclude globs
+grep -r --include='b*' --include='f*' the tests/inputs > "$OUT_DIR/the.recursive.include"
+grep -r --exclude='b*' the tests/inputs > "$OUT_DIR/the.recursive.exclude"
+
+# Max count
+grep -m2 -A1 -n The tests/inputs/bustle.txt > "$OUT_DIR/bustle.txt.the.capitalized.m2.after1.n"
+grep -m2 -c -i the $DIR/bustle.txt $DIR/nobody.txt > "$OUT_DIR/bustle.nobody.the.insensitive.m2.count"
+
+# NUL after file names
+grep -Z -n -i the $DIR/bustle.txt $DIR/fox.txt > "$OUT_DIR/bustle.fox.the.insensitive.n.null"
+grep -Z -l -i the $DIR/*.txt > "$OUT_DIR/all.the.insensitive.l.null"
+grep -Z -c -i the $DIR/*.txt > "$OUT_DIR/all.the.insensitive.count.null"
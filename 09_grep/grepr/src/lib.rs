@@ -1,19 +1,453 @@
-use clap::ArgGroup;
-use clap::{builder::PossibleValue, Arg, ArgAction, Command};
-use regex::{Regex, RegexBuilder};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
-use std::{error::Error, ops::Range};
+use aho_corasick::{AhoCorasick, MatchKind};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::{builder::PossibleValue, value_parser, Arg, ArgAction, ArgMatches, Command};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use regex::bytes::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{
+    self, BufRead, BufReader, BufWriter, Cursor, IsTerminal, Read, Seek, SeekFrom, Write,
+};
+use std::mem;
+use std::ops::Range;
+use std::path::{self, Path, PathBuf};
+use std::process;
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
 use walkdir::WalkDir;
 
 #[derive(Debug)]
 pub struct Config {
-    pattern: Regex,
-    files: Vec<String>,
+    pattern: Box<dyn Matcher>,
+    files: Vec<PathBuf>,
     recursive: bool,
+    include: GlobSet,
+    exclude: GlobSet,
+    exclude_dir: GlobSet,
+    no_ignore: bool,
+    hidden: bool,
     count: bool,
+    count_matches: bool,
     invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    line_number: bool,
+    byte_offset: bool,
+    files_with_matches: bool,
+    files_without_match: bool,
+    only_matching: bool,
+    colors: Option<Colors>,
+    binary_files: BinaryFiles,
+    threads: usize,
+    sort_path: bool,
+    quiet: bool,
+    no_messages: bool,
+    max_count: Option<usize>,
+    json: bool,
+    replace: Option<Vec<u8>>,
+    in_place: bool,
+    multiline: bool,
+    search_zip: bool,
+    null: bool,
+}
+
+impl Config {
+    /// Whether a file passes the --include and --exclude globs.
+    fn wants_file(&self, name: &OsStr) -> bool {
+        (self.include.is_empty() || self.include.is_match(name)) && !self.exclude.is_match(name)
+    }
+
+    /// The lines of leading and trailing context to print.
+    fn context(&self) -> (usize, usize) {
+        // context lines would be counted as matches, so -c ignores them like grep
+        if self.count || self.only_matching {
+            (0, 0)
+        } else {
+            (self.before_context, self.after_context)
+        }
+    }
+}
+
+/// A compiled search, whichever engine runs it.
+trait Matcher: fmt::Debug + Send + Sync {
+    fn is_match(&self, text: &[u8]) -> bool;
+
+    /// Byte ranges of the non-overlapping matches in `text`, empty ones too.
+    fn spans<'a>(&'a self, text: &'a [u8]) -> Box<dyn Iterator<Item = Range<usize>> + 'a>;
+
+    /// Each nonempty match in `text` with `template` expanded for it, `$1` and
+    /// `${name}` standing for its capture groups.
+    fn expand<'a>(
+        &'a self,
+        text: &'a [u8],
+        template: &'a [u8],
+    ) -> Vec<(Range<usize>, Cow<'a, [u8]>)>;
+
+    /// Byte ranges of the non-overlapping, nonempty matches in `text`.
+    fn find_iter<'a>(&'a self, text: &'a [u8]) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        Box::new(self.spans(text).filter(|m| !m.is_empty()))
+    }
+
+    /// For each line of `text`, indexed by line number, how many nonempty
+    /// matches start on it, or `None` if no match touches it. A match that
+    /// spans lines selects all of them, as -U does.
+    fn matched_lines(&self, text: &[u8]) -> Vec<Option<usize>> {
+        let starts: Vec<usize> = std::iter::once(0)
+            .chain(
+                text.iter()
+                    .enumerate()
+                    .filter(|(_, &b)| b == b'\n')
+                    .map(|(i, _)| i + 1),
+            )
+            .collect();
+        // number of the line holding the byte at `offset`
+        let line_at = |offset: usize| starts.partition_point(|&start| start <= offset);
+
+        let mut matched = vec![None; starts.len() + 1];
+        for m in self.spans(text) {
+            let last = if m.is_empty() { m.start } else { m.end - 1 };
+            let first = line_at(m.start);
+            for lines in &mut matched[first..=line_at(last)] {
+                lines.get_or_insert(0);
+            }
+            if !m.is_empty() {
+                *matched[first].as_mut().unwrap() += 1;
+            }
+        }
+        matched
+    }
+
+    /// Each nonempty match in `text` with what to print for it: the matched
+    /// text itself, or `template` expanded as `expand` does.
+    fn replacements<'a>(
+        &'a self,
+        text: &'a [u8],
+        template: Option<&'a [u8]>,
+    ) -> Vec<(Range<usize>, Cow<'a, [u8]>)> {
+        match template {
+            Some(template) => self.expand(text, template),
+            None => self
+                .find_iter(text)
+                .map(|m| (m.clone(), Cow::Borrowed(&text[m])))
+                .collect(),
+        }
+    }
+
+    /// `text` with every match replaced as `replacements` says.
+    fn replace_all(&self, text: &[u8], template: &[u8]) -> Vec<u8> {
+        let mut replaced = Vec::with_capacity(text.len());
+        let mut last = 0;
+        for (m, replacement) in self.replacements(text, Some(template)) {
+            replaced.extend_from_slice(&text[last..m.start]);
+            replaced.extend_from_slice(&replacement);
+            last = m.end;
+        }
+        replaced.extend_from_slice(&text[last..]);
+        replaced
+    }
+}
+
+/// How the patterns are to be compiled, whichever the engine.
+#[derive(Debug, Clone, Copy)]
+struct PatternOptions {
+    insensitive: bool,
+    boundary: Boundary,
+    /// `^` and `$` match at every line of the text searched
+    multiline: bool,
+    /// `.` matches newlines too
+    dotall: bool,
+}
+
+/// How much of the text a match has to cover, as -w and -x ask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Boundary {
+    None,
+    Word,
+    Line,
+}
+
+impl Boundary {
+    /// `pattern` as a group that has to match within the boundary. Like grep,
+    /// -w only asks that no word character be next to the match, so that a
+    /// pattern may start or end with punctuation.
+    fn wrap(self, pattern: &str) -> String {
+        match self {
+            Boundary::None => format!("(?:{})", pattern),
+            Boundary::Word => format!(r"\b{{start-half}}(?:{})\b{{end-half}}", pattern),
+            Boundary::Line => format!("^(?:{})$", pattern),
+        }
+    }
+}
+
+/// What the patterns are joined into when there are none, as with
+/// `-f /dev/null`: the empty alternation would match everywhere.
+const NO_MATCH: &str = r"[^\s\S]";
+
+/// The compiled search patterns.
+#[derive(Debug)]
+enum Pattern {
+    /// Regular expressions: the set decides whether a line matches, the
+    /// alternation of all of them finds where.
+    Regex { set: RegexSet, spans: Regex },
+    /// Literal strings given with -F
+    Fixed(AhoCorasick),
+}
+
+impl Pattern {
+    /// Compiles the patterns, or says why the first invalid one is.
+    fn new(patterns: &[String], fixed: bool, options: PatternOptions) -> Result<Self, String> {
+        let PatternOptions {
+            insensitive,
+            boundary,
+            multiline,
+            dotall,
+        } = options;
+        // Aho-Corasick only folds ASCII case and knows nothing of word or line
+        // boundaries, so leave those to the regex engine
+        let ascii_only = !(insensitive && patterns.iter().any(|p| !p.is_ascii()));
+        if fixed && ascii_only && boundary == Boundary::None {
+            return AhoCorasick::builder()
+                .match_kind(MatchKind::LeftmostLongest)
+                .ascii_case_insensitive(insensitive)
+                .build(patterns)
+                .map(Pattern::Fixed)
+                .map_err(|e| e.to_string());
+        }
+
+        let patterns: Vec<String> = if fixed {
+            patterns.iter().map(|p| regex::escape(p)).collect()
+        } else {
+            patterns.to_vec()
+        };
+        // wrapped and joined, an invalid pattern like `a)(b` could pass
+        for pattern in &patterns {
+            Regex::new(pattern).map_err(|e| invalid_pattern(pattern, e))?;
+        }
+        let wrapped: Vec<String> = patterns.iter().map(|p| boundary.wrap(p)).collect();
+        let set = RegexSetBuilder::new(&wrapped)
+            .case_insensitive(insensitive)
+            .multi_line(multiline)
+            .dot_matches_new_line(dotall)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let alternation = if wrapped.is_empty() {
+            NO_MATCH.to_string()
+        } else {
+            wrapped.join("|")
+        };
+        let spans = RegexBuilder::new(&alternation)
+            .case_insensitive(insensitive)
+            .multi_line(multiline)
+            .dot_matches_new_line(dotall)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Pattern::Regex { set, spans })
+    }
+}
+
+impl Matcher for Pattern {
+    fn is_match(&self, text: &[u8]) -> bool {
+        match self {
+            Pattern::Regex { set, .. } => set.is_match(text),
+            Pattern::Fixed(ac) => ac.is_match(text),
+        }
+    }
+
+    fn spans<'a>(&'a self, text: &'a [u8]) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        match self {
+            Pattern::Regex { spans, .. } => Box::new(spans.find_iter(text).map(|m| m.range())),
+            Pattern::Fixed(ac) => Box::new(ac.find_iter(text).map(|m| m.range())),
+        }
+    }
+
+    /// Fixed strings have no groups, so they get the template as is.
+    fn expand<'a>(
+        &'a self,
+        text: &'a [u8],
+        template: &'a [u8],
+    ) -> Vec<(Range<usize>, Cow<'a, [u8]>)> {
+        match self {
+            Pattern::Regex { spans, .. } => spans
+                .captures_iter(text)
+                .filter_map(|caps| {
+                    let m = caps.get(0).filter(|m| !m.is_empty())?;
+                    let mut replacement = vec![];
+                    caps.expand(template, &mut replacement);
+                    Some((m.range(), Cow::Owned(replacement)))
+                })
+                .collect(),
+            Pattern::Fixed(_) => self
+                .find_iter(text)
+                .map(|m| (m, Cow::Borrowed(template)))
+                .collect(),
+        }
+    }
+}
+
+/// Patterns for -P, run by a backtracking engine that knows lookaround and
+/// backreferences. It works on UTF-8 text only: other lines are matched as if
+/// decoded lossily, and no match spans can be reported in them.
+#[cfg(feature = "pcre2")]
+#[derive(Debug)]
+struct Backtracking(fancy_regex::Regex);
+
+#[cfg(feature = "pcre2")]
+impl Backtracking {
+    /// Compiles the patterns, or says why the first invalid one is.
+    fn new(patterns: &[String], options: PatternOptions) -> Result<Self, String> {
+        for pattern in patterns {
+            fancy_regex::Regex::new(pattern).map_err(|e| invalid_pattern(pattern, e))?;
+        }
+        let flags: String = [
+            (options.insensitive, 'i'),
+            (options.multiline, 'm'),
+            (options.dotall, 's'),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, flag)| *flag)
+        .collect();
+        let wrapped: Vec<String> = patterns
+            .iter()
+            .map(|p| match options.boundary {
+                // fancy-regex knows no half word boundaries, but lookaround
+                Boundary::Word => format!(r"(?<!\w)(?:{})(?!\w)", p),
+                boundary => boundary.wrap(p),
+            })
+            .collect();
+        let alternation = if wrapped.is_empty() {
+            NO_MATCH.to_string()
+        } else {
+            wrapped.join("|")
+        };
+        let pattern = match flags.as_str() {
+            "" => alternation,
+            flags => format!("(?{}){}", flags, alternation),
+        };
+        fancy_regex::Regex::new(&pattern)
+            .map(Backtracking)
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "pcre2")]
+impl Matcher for Backtracking {
+    fn is_match(&self, text: &[u8]) -> bool {
+        // a search that backtracks too much counts as no match
+        self.0
+            .is_match(&String::from_utf8_lossy(text))
+            .unwrap_or(false)
+    }
+
+    fn spans<'a>(&'a self, text: &'a [u8]) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        match std::str::from_utf8(text) {
+            Ok(text) => Box::new(self.0.find_iter(text).map_while(|m| Some(m.ok()?.range()))),
+            Err(_) => Box::new(std::iter::empty()),
+        }
+    }
+
+    fn expand<'a>(
+        &'a self,
+        text: &'a [u8],
+        template: &'a [u8],
+    ) -> Vec<(Range<usize>, Cow<'a, [u8]>)> {
+        let (Ok(text), Ok(template)) = (std::str::from_utf8(text), std::str::from_utf8(template))
+        else {
+            return vec![];
+        };
+        self.0
+            .captures_iter(text)
+            .map_while(Result::ok)
+            .filter_map(|caps| {
+                let m = caps.get(0).filter(|m| m.start() < m.end())?;
+                let mut replacement = String::new();
+                caps.expand(template, &mut replacement);
+                Some((m.range(), Cow::Owned(replacement.into_bytes())))
+            })
+            .collect()
+    }
+}
+
+/// What to do with files that look binary, as --binary-files says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryFiles {
+    /// report "Binary file X matches" instead of the matching lines
+    Binary,
+    /// search them like any text file
+    Text,
+    /// assume they don't match
+    WithoutMatch,
+}
+
+/// SGR parameters for each part of the output, configured like GNU grep
+/// through GREP_COLORS (e.g. `ms=01;31:fn=35:se=36`).
+#[derive(Debug)]
+struct Colors {
+    selected_match: String,
+    context_match: String,
+    file_name: String,
+    line_number: String,
+    byte_offset: String,
+    separator: String,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Colors {
+            selected_match: "01;31".to_string(),
+            context_match: "01;31".to_string(),
+            file_name: "35".to_string(),
+            line_number: "32".to_string(),
+            byte_offset: "32".to_string(),
+            separator: "36".to_string(),
+        }
+    }
+}
+
+impl Colors {
+    fn from_env() -> Self {
+        let mut colors = Colors::default();
+        if let Ok(spec) = env::var("GREP_COLORS") {
+            for capability in spec.split(':') {
+                let (name, value) = capability.split_once('=').unwrap_or((capability, ""));
+                let value = value.to_string();
+                match name {
+                    "mt" => {
+                        colors.selected_match = value.clone();
+                        colors.context_match = value;
+                    }
+                    "ms" => colors.selected_match = value,
+                    "mc" => colors.context_match = value,
+                    "fn" => colors.file_name = value,
+                    "ln" => colors.line_number = value,
+                    "bn" => colors.byte_offset = value,
+                    "se" => colors.separator = value,
+                    // like grep, ignore what we don't support
+                    _ => {}
+                }
+            }
+        }
+        colors
+    }
+}
+
+/// A line read from the input together with its position.
+#[derive(Debug)]
+struct Line {
+    /// 1-based line number
+    number: usize,
+    /// byte offset of the first byte of the line
+    offset: usize,
+    /// raw bytes without the newline; binary files need not be UTF-8
+    text: Vec<u8>,
 }
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
@@ -27,22 +461,89 @@ pub fn get_args() -> MyResult<Config> {
         .args([
             Arg::new("pattern")
                 .value_name("pattern")
-                .default_value("")
-                .required(true)
+                .required_unless_present_any(["regexp", "patterns-file"])
                 .help("pattern to search"),
             Arg::new("file")
                 .value_name("FILE")
                 .help("file to grep")
-                .default_value("-")
+                .value_parser(value_parser!(PathBuf))
                 .num_args(1..),
+            Arg::new("regexp")
+                .short('e')
+                .long("regexp")
+                .value_name("PATTERNS")
+                .help("use PATTERNS for matching")
+                .action(ArgAction::Append),
+            Arg::new("patterns-file")
+                .short('f')
+                .long("file")
+                .value_name("FILE")
+                .help("take PATTERNS from FILE")
+                .action(ArgAction::Append),
+            Arg::new("files-from")
+                .long("files-from")
+                .value_name("FILE")
+                .help("search the files named on each line of FILE"),
+            Arg::new("files0-from")
+                .long("files0-from")
+                .value_name("FILE")
+                .help("search the NUL-separated files named in FILE"),
+            Arg::new("fixed-strings")
+                .short('F')
+                .long("fixed-strings")
+                .help("PATTERNS are strings")
+                .action(ArgAction::SetTrue),
+            Arg::new("perl-regexp")
+                .short('P')
+                .long("perl-regexp")
+                .help("PATTERNS are Perl regular expressions (pcre2 feature)")
+                .conflicts_with("fixed-strings")
+                .action(ArgAction::SetTrue),
+            Arg::new("word-regexp")
+                .short('w')
+                .long("word-regexp")
+                .help("match only whole words")
+                .action(ArgAction::SetTrue),
+            Arg::new("line-regexp")
+                .short('x')
+                .long("line-regexp")
+                .help("match only whole lines")
+                .action(ArgAction::SetTrue),
             Arg::new("recursive")
                 .short('r')
                 .long("recursive")
                 .action(ArgAction::SetTrue),
+            Arg::new("include")
+                .long("include")
+                .value_name("GLOB")
+                .help("search only files that match GLOB")
+                .action(ArgAction::Append),
+            Arg::new("exclude")
+                .long("exclude")
+                .value_name("GLOB")
+                .help("skip files that match GLOB")
+                .action(ArgAction::Append),
+            Arg::new("exclude-dir")
+                .long("exclude-dir")
+                .value_name("GLOB")
+                .help("skip directories that match GLOB")
+                .action(ArgAction::Append),
+            Arg::new("no-ignore")
+                .long("no-ignore")
+                .help("don't skip files ignored by .gitignore, .ignore or .git/info/exclude")
+                .action(ArgAction::SetTrue),
+            Arg::new("hidden")
+                .long("hidden")
+                .help("search hidden files and directories")
+                .action(ArgAction::SetTrue),
             Arg::new("count")
                 .short('c')
                 .long("count")
                 .action(ArgAction::SetTrue),
+            Arg::new("count-matches")
+                .long("count-matches")
+                .help("like -c, but count each match rather than each line")
+                .action(ArgAction::SetTrue),
             Arg::new("invert-match")
                 .short('v')
                 .long("invert-match")
@@ -51,150 +552,1510 @@ pub fn get_args() -> MyResult<Config> {
                 .short('i')
                 .long("insensitive")
                 .action(ArgAction::SetTrue),
+            Arg::new("after-context")
+                .short('A')
+                .long("after-context")
+                .value_name("NUM")
+                .help("print NUM lines of trailing context")
+                .value_parser(value_parser!(usize)),
+            Arg::new("before-context")
+                .short('B')
+                .long("before-context")
+                .value_name("NUM")
+                .help("print NUM lines of leading context")
+                .value_parser(value_parser!(usize)),
+            Arg::new("context")
+                .short('C')
+                .long("context")
+                .value_name("NUM")
+                .help("print NUM lines of output context")
+                .value_parser(value_parser!(usize)),
+            Arg::new("line-number")
+                .short('n')
+                .long("line-number")
+                .help("prefix each line with its line number")
+                .action(ArgAction::SetTrue),
+            Arg::new("byte-offset")
+                .short('b')
+                .long("byte-offset")
+                .help("prefix each line with its byte offset")
+                .action(ArgAction::SetTrue),
+            Arg::new("files-with-matches")
+                .short('l')
+                .long("files-with-matches")
+                .help("print only names of FILEs with selected lines")
+                .conflicts_with("files-without-match")
+                .action(ArgAction::SetTrue),
+            Arg::new("files-without-match")
+                .short('L')
+                .long("files-without-match")
+                .help("print only names of FILEs with no selected lines")
+                .action(ArgAction::SetTrue),
+            Arg::new("null")
+                .short('Z')
+                .long("null")
+                .help("print a NUL byte after file names")
+                .action(ArgAction::SetTrue),
+            Arg::new("only-matching")
+                .short('o')
+                .long("only-matching")
+                .help("show only nonempty parts of lines that match")
+                .action(ArgAction::SetTrue),
+            Arg::new("color")
+                .long("color")
+                .alias("colour")
+                .value_name("WHEN")
+                .help("use markers to highlight the matching strings")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_value("never")
+                .default_missing_value("auto")
+                .value_parser([
+                    PossibleValue::new("auto"),
+                    PossibleValue::new("always"),
+                    PossibleValue::new("never"),
+                ]),
+            Arg::new("binary-files")
+                .long("binary-files")
+                .value_name("TYPE")
+                .help("assume that binary files are TYPE")
+                .default_value("binary")
+                .value_parser([
+                    PossibleValue::new("binary"),
+                    PossibleValue::new("text"),
+                    PossibleValue::new("without-match"),
+                ]),
+            Arg::new("text")
+                .short('a')
+                .long("text")
+                .help("equivalent to --binary-files=text")
+                .action(ArgAction::SetTrue),
+            Arg::new("without-match")
+                .short('I')
+                .help("equivalent to --binary-files=without-match")
+                .action(ArgAction::SetTrue),
+            Arg::new("threads")
+                .short('j')
+                .long("threads")
+                .value_name("NUM")
+                .help("search NUM files at once (0: one per CPU)")
+                .default_value("0")
+                .value_parser(value_parser!(usize)),
+            Arg::new("max-count")
+                .short('m')
+                .long("max-count")
+                .value_name("NUM")
+                .help("stop after NUM selected lines")
+                .value_parser(value_parser!(usize)),
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .alias("silent")
+                .help("suppress all normal output")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-messages")
+                .short('s')
+                .long("no-messages")
+                .help("suppress error messages")
+                .action(ArgAction::SetTrue),
+            Arg::new("sort")
+                .long("sort")
+                .value_name("SORTBY")
+                .help("sort the files found in directories")
+                .default_value("none")
+                .value_parser([PossibleValue::new("path"), PossibleValue::new("none")]),
+            Arg::new("json")
+                .long("json")
+                .help("print results as JSON Lines")
+                .conflicts_with_all([
+                    "count",
+                    "count-matches",
+                    "files-with-matches",
+                    "files-without-match",
+                ])
+                .action(ArgAction::SetTrue),
+            Arg::new("replace")
+                .long("replace")
+                .value_name("TEMPLATE")
+                .help("print matches replaced by TEMPLATE ($1, ${name} for groups)"),
+            Arg::new("in-place")
+                .long("in-place")
+                .help("rewrite the files with the replacements instead")
+                .requires("replace")
+                .conflicts_with_all(["invert-match", "json", "quiet"])
+                .action(ArgAction::SetTrue),
+            Arg::new("multiline")
+                .short('U')
+                .long("multiline")
+                .help("let matches span lines")
+                .action(ArgAction::SetTrue),
+            Arg::new("multiline-dotall")
+                .long("multiline-dotall")
+                .help("let '.' match newlines with -U")
+                .requires("multiline")
+                .action(ArgAction::SetTrue),
+            Arg::new("search-zip")
+                .short('z')
+                .long("search-zip")
+                .help("search in gzip, bzip2, xz and zstd compressed files")
+                .conflicts_with("in-place")
+                .action(ArgAction::SetTrue),
         ])
         .get_matches();
 
     let insensitive = *matches.get_one::<bool>("insensitive").unwrap();
+    // -A and -B take precedence over -C
+    let context = matches.get_one::<usize>("context").copied().unwrap_or(0);
+
+    let mut files: Vec<PathBuf> = match matches.get_many::<PathBuf>("file") {
+        Some(files) => files.cloned().collect(),
+        None => vec![],
+    };
+    let mut patterns: Vec<String> = match matches.get_many::<String>("regexp") {
+        Some(patterns) => patterns.map(|s| s.to_string()).collect(),
+        None => vec![],
+    };
+    if let Some(pattern_files) = matches.get_many::<String>("patterns-file") {
+        for pattern_file in pattern_files {
+            let reader =
+                open(Path::new(pattern_file)).map_err(|e| format!("{}: {}", pattern_file, e))?;
+            for line in reader.lines() {
+                patterns.push(line?);
+            }
+        }
+    }
+    // with -e or -f the first positional argument is a file, not a pattern
+    if let Some(pattern) = matches.get_one::<String>("pattern") {
+        if matches.contains_id("regexp") || matches.contains_id("patterns-file") {
+            files.insert(0, PathBuf::from(pattern));
+        } else {
+            patterns.push(pattern.to_string());
+        }
+    }
+    // listed files go after those on the command line
+    let mut listed = false;
+    for (id, separator) in [("files-from", b'\n'), ("files0-from", b'\0')] {
+        if let Some(list) = matches.get_one::<String>(id) {
+            files.extend(read_file_list(list, separator)?);
+            listed = true;
+        }
+    }
+    if files.is_empty() && !listed {
+        files.push(PathBuf::from("-"));
+    }
+
+    let fixed = *matches.get_one::<bool>("fixed-strings").unwrap();
+    let multiline = *matches.get_one::<bool>("multiline").unwrap();
+    let dotall = *matches.get_one::<bool>("multiline-dotall").unwrap();
+    let count_matches = *matches.get_one::<bool>("count-matches").unwrap();
+    // -x wins over -w, as in grep
+    let boundary = if *matches.get_one::<bool>("line-regexp").unwrap() {
+        Boundary::Line
+    } else if *matches.get_one::<bool>("word-regexp").unwrap() {
+        Boundary::Word
+    } else {
+        Boundary::None
+    };
+    let options = PatternOptions {
+        insensitive,
+        boundary,
+        multiline,
+        dotall,
+    };
+    let perl = *matches.get_one::<bool>("perl-regexp").unwrap();
+    if perl && !cfg!(feature = "pcre2") {
+        return Err("-P is not supported: grepr was built without the pcre2 feature".into());
+    }
 
     Ok(Config {
-        pattern: match matches.get_one::<String>("pattern") {
-            Some(pattern) => match RegexBuilder::new(pattern)
-                .case_insensitive(insensitive)
-                .build()
-            {
-                Ok(re) => re,
-                Err(_) => {
-                    eprintln!("Invalid pattern \"{}\"", pattern);
-                    std::process::exit(1);
-                }
-            },
-            None => Regex::new("")?,
-        },
-        files: match matches.get_many::<String>("file") {
-            Some(files) => files.map(|s| s.to_string()).collect(),
-            None => vec![],
+        pattern: match matcher(&patterns, fixed, perl, options) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
         },
+        files,
         recursive: *matches.get_one::<bool>("recursive").unwrap(),
-        count: *matches.get_one::<bool>("count").unwrap(),
+        include: glob_set(&matches, "include")?,
+        exclude: glob_set(&matches, "exclude")?,
+        exclude_dir: glob_set(&matches, "exclude-dir")?,
+        no_ignore: *matches.get_one::<bool>("no-ignore").unwrap(),
+        hidden: *matches.get_one::<bool>("hidden").unwrap(),
+        count: *matches.get_one::<bool>("count").unwrap() || count_matches,
+        count_matches,
         invert_match: *matches.get_one::<bool>("invert-match").unwrap(),
+        before_context: matches
+            .get_one::<usize>("before-context")
+            .copied()
+            .unwrap_or(context),
+        after_context: matches
+            .get_one::<usize>("after-context")
+            .copied()
+            .unwrap_or(context),
+        line_number: *matches.get_one::<bool>("line-number").unwrap(),
+        byte_offset: *matches.get_one::<bool>("byte-offset").unwrap(),
+        files_with_matches: *matches.get_one::<bool>("files-with-matches").unwrap(),
+        files_without_match: *matches.get_one::<bool>("files-without-match").unwrap(),
+        only_matching: *matches.get_one::<bool>("only-matching").unwrap(),
+        colors: match matches.get_one::<String>("color").unwrap().as_str() {
+            "always" => Some(Colors::from_env()),
+            "auto" if io::stdout().is_terminal() => Some(Colors::from_env()),
+            _ => None,
+        },
+        binary_files: if *matches.get_one::<bool>("text").unwrap() {
+            BinaryFiles::Text
+        } else if *matches.get_one::<bool>("without-match").unwrap() {
+            BinaryFiles::WithoutMatch
+        } else {
+            match matches.get_one::<String>("binary-files").unwrap().as_str() {
+                "text" => BinaryFiles::Text,
+                "without-match" => BinaryFiles::WithoutMatch,
+                _ => BinaryFiles::Binary,
+            }
+        },
+        threads: match *matches.get_one::<usize>("threads").unwrap() {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        },
+        sort_path: matches.get_one::<String>("sort").unwrap() == "path",
+        quiet: *matches.get_one::<bool>("quiet").unwrap(),
+        no_messages: *matches.get_one::<bool>("no-messages").unwrap(),
+        max_count: matches.get_one::<usize>("max-count").copied(),
+        json: *matches.get_one::<bool>("json").unwrap(),
+        replace: matches
+            .get_one::<String>("replace")
+            .map(|template| template.as_bytes().to_vec()),
+        in_place: *matches.get_one::<bool>("in-place").unwrap(),
+        multiline,
+        search_zip: *matches.get_one::<bool>("search-zip").unwrap(),
+        null: *matches.get_one::<bool>("null").unwrap(),
+    })
+}
+
+/// The file names in `list`, each followed by `separator`.
+fn read_file_list(list: &str, separator: u8) -> MyResult<Vec<PathBuf>> {
+    let reader = open(Path::new(list)).map_err(|e| format!("{}: {}", list, e))?;
+    let mut files = vec![];
+    for name in reader.split(separator) {
+        let name = name?;
+        if !name.is_empty() {
+            files.push(path_from_bytes(name));
+        }
+    }
+    Ok(files)
+}
+
+/// A file name as read from a list; any bytes will do on Unix.
+#[cfg(unix)]
+fn path_from_bytes(name: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(name))
+}
+
+/// Elsewhere names must be UTF-8; others are kept lossily, so that opening
+/// them fails and is reported like any missing file.
+#[cfg(not(unix))]
+fn path_from_bytes(name: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&name).into_owned())
+}
+
+/// Compiles the patterns with the engine asked for, or says why the first
+/// invalid one is.
+fn matcher(
+    patterns: &[String],
+    fixed: bool,
+    perl: bool,
+    options: PatternOptions,
+) -> Result<Box<dyn Matcher>, String> {
+    match perl {
+        #[cfg(feature = "pcre2")]
+        true => Ok(Box::new(Backtracking::new(patterns, options)?)),
+        _ => Ok(Box::new(Pattern::new(patterns, fixed, options)?)),
+    }
+}
+
+fn invalid_pattern(pattern: &str, e: impl fmt::Display) -> String {
+    format!("Invalid pattern \"{}\": {}", pattern, e)
+}
+
+fn glob_set(matches: &ArgMatches, id: &str) -> MyResult<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    if let Some(globs) = matches.get_many::<String>(id) {
+        for glob in globs {
+            builder.add(Glob::new(glob)?);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// Searches the files and returns the exit status, as grep does: 0 if a line
+/// was selected, 1 if none was, and 2 if a file could not be read.
+pub fn run(cfg: Config) -> MyResult<i32> {
+    let mut printer = Printer::new(BufWriter::new(io::stdout().lock()), &cfg);
+    let readable = match search_all(&cfg, &mut printer).and_then(|readable| {
+        printer.out.flush()?;
+        Ok(readable)
+    }) {
+        // the reader went away (e.g. `grepr foo huge.log | head`), not an error
+        Err(e) if is_broken_pipe(e.as_ref()) => true,
+        result => result?,
+    };
+
+    Ok(if !readable || printer.failed {
+        2
+    } else if printer.matched {
+        0
+    } else {
+        1
+    })
+}
+
+fn is_broken_pipe(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// Searches every file, returning whether all the paths could be walked.
+fn search_all<W: Write>(cfg: &Config, printer: &mut Printer<'_, W>) -> MyResult<bool> {
+    // a single file has nothing to search alongside it
+    if cfg.threads == 1 || (cfg.files.len() == 1 && !cfg.recursive) {
+        return walk(cfg, |path, multiple| search(printer, path, cfg, multiple));
+    }
+
+    // the walk hands files out to the workers, each of which prints a file
+    // into its own spool so that files never interleave
+    let (job_tx, job_rx) = mpsc::channel::<(usize, PathBuf, bool)>();
+    let (done_tx, done_rx) = mpsc::sync_channel(cfg.threads);
+    let job_rx = Mutex::new(job_rx);
+    let turn = Turn::default();
+    thread::scope(|scope| {
+        for _ in 0..cfg.threads {
+            let (job_rx, done_tx, turn) = (&job_rx, done_tx.clone(), &turn);
+            scope.spawn(move || loop {
+                // the queue must be unlocked again before the file is searched
+                let job = job_rx.lock().unwrap().recv();
+                let Ok((seq, path, multiple)) = job else {
+                    break;
+                };
+                let mut file_printer = Printer::new(Spool::new(seq, turn, &done_tx), cfg);
+                let searched = search(&mut file_printer, &path, cfg, multiple);
+                let out = file_printer.out.finish();
+                let result = match searched {
+                    Ok(()) => Ok(file_printer.with_out(out)),
+                    Err(e) => {
+                        turn.release(out.len());
+                        Err(format!("{}: {}", message_name(&path), e))
+                    }
+                };
+                if done_tx.send(Done::File(seq, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(done_tx);
+
+        let readable = dispatch(cfg, printer, job_tx, &done_rx, &turn);
+        // wake the workers still waiting for a turn that will not come
+        drop(done_rx);
+        turn.close();
+        readable
     })
 }
 
-pub fn run(cfg: Config) -> MyResult<()> {
+/// Hands the files to the workers as they are walked and prints what the
+/// workers send back.
+fn dispatch<'a, W: Write>(
+    cfg: &Config,
+    printer: &mut Printer<'_, W>,
+    job_tx: mpsc::Sender<(usize, PathBuf, bool)>,
+    done_rx: &mpsc::Receiver<Done<'a>>,
+    turn: &Turn,
+) -> MyResult<bool> {
+    let mut collator = Collator::new(turn);
+    let mut seq = 0;
+    let walked = walk(cfg, |path, multiple| {
+        job_tx.send((seq, path.to_path_buf(), multiple))?;
+        seq += 1;
+        done_rx
+            .try_iter()
+            .try_for_each(|done| collator.print(printer, done))
+    });
+    // the workers stop once the queue is closed and empty
+    drop(job_tx);
+    let readable = walked?;
+
+    for done in done_rx {
+        collator.print(printer, done)?;
+    }
+    Ok(readable)
+}
+
+/// How many bytes of output the workers may hold back in all while the files
+/// before theirs are printed.
+const HELD_LIMIT: usize = 4 << 20;
+
+/// How much a worker holds back or sends on at a time.
+const SPOOL_BLOCK: usize = 64 << 10;
+
+/// What a worker sends back.
+enum Done<'a> {
+    /// more output of the file whose turn it is to be printed
+    Part(Vec<u8>),
+    /// a searched file, with the output it has not sent yet
+    File(usize, FileOutput<'a>),
+}
+
+/// A file searched by a worker: its output, or why searching it failed.
+type FileOutput<'a> = Result<Printer<'a, Vec<u8>>, String>;
+
+/// Which file is being printed, shared by the workers and the thread that
+/// prints.
+#[derive(Default)]
+struct Turn {
+    state: Mutex<TurnState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct TurnState {
+    /// the file being printed
+    next: usize,
+    /// bytes held back for the files after it
+    held: usize,
+    /// set once nothing more will be printed
+    closed: bool,
+}
+
+impl Turn {
+    /// Gives back `bytes` held for a file that is done with.
+    fn release(&self, bytes: usize) {
+        self.state.lock().unwrap().held -= bytes;
+        self.changed.notify_all();
+    }
+
+    /// Makes it the turn of file `next`.
+    fn pass(&self, next: usize) {
+        self.state.lock().unwrap().next = next;
+        self.changed.notify_all();
+    }
+
+    /// Wakes the workers waiting for a turn, as nothing more is printed.
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// The output of one file searched by a worker. It is held back until the
+/// files before it have been printed, but once too much is held the worker
+/// waits for the file's turn and then sends the output on as it goes.
+struct Spool<'s, 'a> {
+    seq: usize,
+    buf: Vec<u8>,
+    /// bytes of `HELD_LIMIT` taken for `buf`
+    reserved: usize,
+    /// set once it is this file's turn
+    streaming: bool,
+    turn: &'s Turn,
+    done_tx: &'s mpsc::SyncSender<Done<'a>>,
+}
+
+impl<'s, 'a> Spool<'s, 'a> {
+    fn new(seq: usize, turn: &'s Turn, done_tx: &'s mpsc::SyncSender<Done<'a>>) -> Self {
+        Spool {
+            seq,
+            buf: vec![],
+            reserved: 0,
+            streaming: false,
+            turn,
+            done_tx,
+        }
+    }
+
+    /// Takes more of the limit for `len` more bytes or, once too much is
+    /// held, waits for this file's turn.
+    fn reserve(&mut self, len: usize) -> io::Result<()> {
+        let mut state = self.turn.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            if state.next == self.seq {
+                // nothing is held back from here on
+                state.held -= self.reserved;
+                self.reserved = 0;
+                self.streaming = true;
+                return Ok(());
+            }
+            let more = len.max(SPOOL_BLOCK);
+            if state.held + more <= HELD_LIMIT {
+                state.held += more;
+                self.reserved += more;
+                return Ok(());
+            }
+            state = self.turn.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Takes the output not sent yet, leaving just its size held until it is
+    /// printed.
+    fn finish(&mut self) -> Vec<u8> {
+        let out = mem::take(&mut self.buf);
+        let mut state = self.turn.state.lock().unwrap();
+        state.held = state.held - self.reserved + out.len();
+        self.reserved = 0;
+        drop(state);
+        self.turn.changed.notify_all();
+        out
+    }
+}
+
+impl Write for Spool<'_, '_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if !self.streaming && self.buf.len() + data.len() > self.reserved {
+            self.reserve(data.len())?;
+        }
+        self.buf.extend_from_slice(data);
+        if self.streaming && self.buf.len() >= SPOOL_BLOCK {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.streaming && !self.buf.is_empty() {
+            let part = Done::Part(mem::take(&mut self.buf));
+            self.done_tx
+                .send(part)
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints what the workers send in the order the files were found.
+struct Collator<'a, 't> {
+    turn: &'t Turn,
+    /// files searched before their turn
+    pending: BTreeMap<usize, FileOutput<'a>>,
+    /// the file whose turn it is
+    next: usize,
+    /// set once some of that file's output is printed
+    started: bool,
+}
+
+impl<'a, 't> Collator<'a, 't> {
+    fn new(turn: &'t Turn) -> Self {
+        Collator {
+            turn,
+            pending: BTreeMap::new(),
+            next: 0,
+            started: false,
+        }
+    }
+
+    /// Prints the output of the file whose turn it is as it comes, and any
+    /// other file once the ones before it are printed. A file that failed is
+    /// reported and the others still printed.
+    fn print<W: Write>(&mut self, printer: &mut Printer<'_, W>, done: Done<'a>) -> MyResult<()> {
+        match done {
+            Done::Part(part) => {
+                if !self.started {
+                    printer.separate_file()?;
+                    self.started = true;
+                }
+                printer.out.write_all(&part)?;
+            }
+            Done::File(seq, result) => {
+                self.pending.insert(seq, result);
+                while let Some(result) = self.pending.remove(&self.next) {
+                    match result {
+                        Ok(file) => {
+                            self.turn.release(file.out.len());
+                            printer.append(file, self.started)?;
+                        }
+                        Err(e) => {
+                            if !printer.cfg.no_messages {
+                                eprintln!("{}", e);
+                            }
+                            printer.failed = true;
+                        }
+                    }
+                    self.next += 1;
+                    self.started = false;
+                    self.turn.pass(self.next);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Calls `visit` with each file to search and whether its name has to be
+/// printed, walking directories with -r. Returns false if a path could not be
+/// read.
+fn walk(cfg: &Config, mut visit: impl FnMut(&Path, bool) -> MyResult<()>) -> MyResult<bool> {
     let multiple = cfg.files.len() > 1;
-    let mut matched: Vec<String> = vec![];
-    for file in cfg.files {
-        if file == "-" {
-            process_file(&mut matched, "-", &cfg.pattern, multiple, cfg.invert_match)?;
+    let mut readable = true;
+    for file in &cfg.files {
+        if is_stdin(file) {
+            visit(file, multiple)?;
             continue;
         }
 
-        for entry in WalkDir::new(file) {
+        let max_depth = if cfg.recursive { usize::MAX } else { 0 };
+        let root = path::absolute(file)?;
+        let mut ignores = (cfg.recursive && !cfg.no_ignore).then(|| IgnoreStack::new(&root));
+        let mut walker = WalkDir::new(file).max_depth(max_depth);
+        if cfg.sort_path {
+            walker = walker.sort_by_file_name();
+        }
+        let mut walker = walker.into_iter();
+        while let Some(entry) = walker.next() {
             match entry {
                 Ok(entry) => {
-                    let path = entry.path().to_string_lossy();
-                    if entry.file_type().is_file() {
-                        process_file(
-                            &mut matched,
-                            &path,
-                            &cfg.pattern,
-                            multiple,
-                            cfg.invert_match,
-                        )?;
-                    } else if entry.file_type().is_dir() && cfg.recursive {
-                        if !cfg.recursive {
+                    let path = entry.path();
+                    let abs = root.join(entry.path().strip_prefix(file)?);
+                    // globs are matched against the base name, like grep does
+                    let name = entry.file_name();
+                    let is_dir = entry.file_type().is_dir();
+
+                    // paths given on the command line are always searched
+                    if entry.depth() > 0 {
+                        let hidden = name.to_string_lossy().starts_with('.');
+                        let ignored = ignores
+                            .as_mut()
+                            .is_some_and(|ignores| ignores.is_ignored(&abs, entry.depth(), is_dir));
+                        if (hidden && !cfg.hidden) || ignored {
+                            if is_dir {
+                                walker.skip_current_dir();
+                            }
                             continue;
                         }
+                    }
 
-                        for file in WalkDir::new(entry.path()) {
-                            match file {
-                                Ok(file) => {
-                                    if !file.path().is_file() {
-                                        continue;
-                                    }
-                                    let path = file.path().to_string_lossy();
-                                    if file.file_type().is_file() {
-                                        process_file(
-                                            &mut matched,
-                                            &path,
-                                            &cfg.pattern,
-                                            true,
-                                            cfg.invert_match,
-                                        )?;
-                                    }
-                                }
-                                Err(e) => eprintln!("{}", e),
+                    if is_dir {
+                        if !cfg.recursive {
+                            readable = false;
+                            if !cfg.no_messages {
+                                eprintln!("{} is a directory", path.display());
                             }
+                        } else if entry.depth() > 0 && cfg.exclude_dir.is_match(name) {
+                            walker.skip_current_dir();
+                        } else if let Some(ignores) = ignores.as_mut() {
+                            ignores.enter(&abs, entry.depth());
                         }
+                    } else if (entry.file_type().is_file() || entry.depth() == 0)
+                        && cfg.wants_file(name)
+                    {
+                        // like grep, FIFOs and devices are only read when named;
+                        // files found under a directory are always prefixed
+                        visit(path, multiple || entry.depth() > 0)?;
+                    }
+                }
+                Err(e) => {
+                    readable = false;
+                    if !cfg.no_messages {
+                        eprintln!("{}", e);
                     }
                 }
-                Err(e) => eprintln!("{}", e),
             }
         }
     }
 
-    let has = matched
-        .iter()
-        .filter(|s| !s.is_empty())
-        .map(|s| s.to_owned())
-        .collect::<Vec<String>>();
+    Ok(readable)
+}
+
+/// The .gitignore, .ignore and .git/info/exclude rules in effect at the
+/// current point of a directory walk.
+struct IgnoreStack {
+    /// rules from the directories above the walk's root, up to the repository
+    parents: Vec<Gitignore>,
+    /// rules from the directories being walked, with their depth, innermost last
+    dirs: Vec<(usize, Gitignore)>,
+}
+
+impl IgnoreStack {
+    /// Starts a walk at the absolute path `root`.
+    fn new(root: &Path) -> Self {
+        let mut parents = vec![];
+        // only the repository containing the root matters, if there is one,
+        // and none of it lies above a root that is the repository itself
+        if let Some(repo) = root.ancestors().find(|dir| dir.join(".git").exists()) {
+            for dir in root
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| dir.starts_with(repo))
+            {
+                parents.extend(read_ignore_rules(dir));
+            }
+        }
+        parents.reverse();
+        IgnoreStack {
+            parents,
+            dirs: vec![],
+        }
+    }
 
-    if has.len() == 0 {
+    /// Reads the rules of a directory the walk descends into.
+    fn enter(&mut self, dir: &Path, depth: usize) {
+        self.dirs
+            .extend(read_ignore_rules(dir).map(|rules| (depth, rules)));
+    }
+
+    /// Whether the entry at `path`, `depth` levels below the root, is ignored.
+    /// The innermost rule that matches decides, so `!` can re-include paths.
+    fn is_ignored(&mut self, path: &Path, depth: usize, is_dir: bool) -> bool {
+        // rules of directories the walk has left no longer apply
+        self.dirs.retain(|(dir_depth, _)| *dir_depth < depth);
+        let dirs = self.dirs.iter().rev().map(|(_, rules)| rules);
+        for rules in dirs.chain(self.parents.iter().rev()) {
+            match rules.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+/// Reads the ignore files of `dir`; later files take precedence over earlier ones.
+fn read_ignore_rules(dir: &Path) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    for file in [".git/info/exclude", ".gitignore", ".ignore"] {
+        let path = dir.join(file);
+        if path.is_file() {
+            if let Some(e) = builder.add(&path) {
+                eprintln!("{}: {}", path.display(), e);
+            }
+        }
+    }
+    builder.build().ok().filter(|rules| !rules.is_empty())
+}
+
+/// Searches one file, printing its selected lines, their count or its name.
+fn search<W: Write>(
+    printer: &mut Printer<'_, W>,
+    file_path: &Path,
+    cfg: &Config,
+    multiple: bool,
+) -> MyResult<()> {
+    let found = if cfg.in_place {
+        rewrite_file(file_path, cfg)?
+    } else {
+        process_file(printer, file_path, cfg, multiple)?
+    };
+    let Some(found) = found else {
+        printer.failed = true;
         return Ok(());
+    };
+
+    let count = found.lines;
+    if count > 0 {
+        printer.matched = true;
+        // nothing else can change the outcome
+        if cfg.quiet {
+            process::exit(0);
+        }
+    }
+    if !cfg.quiet {
+        let name = display_name(file_path);
+        if cfg.files_with_matches || cfg.files_without_match {
+            if (count > 0) == cfg.files_with_matches {
+                printer.file_name(&name)?;
+            }
+        } else if cfg.count {
+            let count = if cfg.count_matches {
+                found.matches
+            } else {
+                count
+            };
+            printer.count(multiple.then_some(&name), count)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_stdin(file_path: &Path) -> bool {
+    file_path.as_os_str() == "-"
+}
+
+/// The name a file is reported under; stdin has no path of its own. Like
+/// grep, the bytes of the name are printed as they are.
+fn display_name(file_path: &Path) -> Cow<'_, [u8]> {
+    if is_stdin(file_path) {
+        Cow::Borrowed(b"(standard input)")
+    } else {
+        path_bytes(file_path)
+    }
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+/// Elsewhere names that are not Unicode are printed lossily.
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(name) => Cow::Borrowed(name.as_bytes()),
+        Cow::Owned(name) => Cow::Owned(name.into_bytes()),
+    }
+}
+
+/// The name of a file in messages on stderr.
+fn message_name(file_path: &Path) -> String {
+    String::from_utf8_lossy(&display_name(file_path)).into_owned()
+}
+
+/// Buffered output shared by every searched file.
+struct Printer<'a, W: Write> {
+    out: W,
+    cfg: &'a Config,
+    /// set once a line has been printed, so that the next context group is
+    /// preceded by "--"
+    printed: bool,
+    /// set once a line has been selected
+    matched: bool,
+    /// set once a file could not be read
+    failed: bool,
+    /// the file being searched, for --json
+    json: JsonFile,
+    /// with -U, the matches in the whole file being searched, each with what
+    /// -o and --replace print for it
+    spans: Vec<(Range<usize>, Vec<u8>)>,
+}
+
+/// What --json reports about the file being searched.
+#[derive(Default)]
+struct JsonFile {
+    path: Vec<u8>,
+    /// set once the "begin" event is out
+    begun: bool,
+    matched_lines: usize,
+    matches: usize,
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    fn new(out: W, cfg: &'a Config) -> Self {
+        Printer {
+            out,
+            cfg,
+            printed: false,
+            matched: false,
+            failed: false,
+            json: JsonFile::default(),
+            spans: vec![],
+        }
     }
 
-    if cfg.count {
-        if multiple {
-            let mut count_hash: HashMap<String, usize> = HashMap::new();
-            for line in has {
-                let key = line.split(":").next().unwrap().to_string();
-                let count = count_hash.entry(key).or_insert(0);
-                *count += 1;
+    /// Writes `text`, wrapped in the given SGR color when coloring is on.
+    fn paint(&mut self, color: impl Fn(&Colors) -> &str, text: impl AsRef<[u8]>) -> io::Result<()> {
+        match self.cfg.colors.as_ref().map(color) {
+            Some(sgr) if !sgr.is_empty() => {
+                write!(self.out, "\x1b[{}m\x1b[K", sgr)?;
+                self.out.write_all(text.as_ref())?;
+                write!(self.out, "\x1b[m\x1b[K")
             }
+            _ => self.out.write_all(text.as_ref()),
+        }
+    }
+
+    fn separator(&mut self) -> io::Result<()> {
+        if self.cfg.json {
+            return Ok(());
+        }
+        self.paint(|c| &c.separator, "--")?;
+        writeln!(self.out)
+    }
+
+    fn file_name(&mut self, name: &[u8]) -> io::Result<()> {
+        self.paint(|c| &c.file_name, name)?;
+        if self.cfg.null {
+            self.out.write_all(b"\0")
+        } else {
+            writeln!(self.out)
+        }
+    }
+
+    /// What follows a file name on a line: `sep`, or NUL with -Z.
+    fn file_name_separator(&mut self, sep: &str) -> io::Result<()> {
+        if self.cfg.null {
+            self.out.write_all(b"\0")
+        } else {
+            self.paint(|c| &c.separator, sep)
+        }
+    }
 
-            for (key, value) in count_hash.iter() {
-                println!("{}:{}", key, value);
+    fn count(&mut self, name: Option<&[u8]>, count: usize) -> io::Result<()> {
+        if let Some(name) = name {
+            self.paint(|c| &c.file_name, name)?;
+            self.file_name_separator(":")?;
+        }
+        writeln!(self.out, "{}", count)
+    }
+
+    /// Copies the output of a file searched on its own, or the rest of it if
+    /// it was `started` already.
+    fn append(&mut self, file: Printer<'_, Vec<u8>>, started: bool) -> io::Result<()> {
+        if !started && file.printed {
+            self.separate_file()?;
+        }
+        self.printed |= file.printed;
+        self.matched |= file.matched;
+        self.failed |= file.failed;
+        self.out.write_all(&file.out)
+    }
+
+    /// Prints "--" before the lines of another file searched on its own, if
+    /// context is printed.
+    fn separate_file(&mut self) -> io::Result<()> {
+        let (before_context, after_context) = self.cfg.context();
+        if (before_context > 0 || after_context > 0) && self.printed {
+            self.separator()?;
+        }
+        Ok(())
+    }
+
+    /// This printer, printing to `out` instead.
+    fn with_out<V: Write>(self, out: V) -> Printer<'a, V> {
+        Printer {
+            out,
+            cfg: self.cfg,
+            printed: self.printed,
+            matched: self.matched,
+            failed: self.failed,
+            json: self.json,
+            spans: self.spans,
+        }
+    }
+
+    fn binary_file_matches(&mut self, name: &[u8]) -> io::Result<()> {
+        self.out.write_all(b"Binary file ")?;
+        self.out.write_all(name)?;
+        writeln!(self.out, " matches")
+    }
+
+    /// file:line:offset:, with '-' instead of ':' for context lines
+    fn prefix(
+        &mut self,
+        name: Option<&[u8]>,
+        number: usize,
+        offset: usize,
+        sep: char,
+    ) -> io::Result<()> {
+        let sep = sep.to_string();
+        if let Some(name) = name {
+            self.paint(|c| &c.file_name, name)?;
+            self.file_name_separator(&sep)?;
+        }
+        if self.cfg.line_number {
+            self.paint(|c| &c.line_number, number.to_string())?;
+            self.paint(|c| &c.separator, &sep)?;
+        }
+        if self.cfg.byte_offset {
+            self.paint(|c| &c.byte_offset, offset.to_string())?;
+            self.paint(|c| &c.separator, &sep)?;
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, name: Option<&[u8]>, line: &Line, sep: char) -> io::Result<()> {
+        if self.cfg.json {
+            return self.json_line(line, sep);
+        }
+        self.prefix(name, line.number, line.offset, sep)?;
+        let cfg = self.cfg;
+        // context lines are printed as they are, only colored
+        let template = cfg.replace.as_deref().filter(|_| sep == ':');
+        if cfg.colors.is_none() && template.is_none() {
+            self.out.write_all(&line.text)?;
+            return writeln!(self.out);
+        }
+
+        let mut last = 0;
+        for (m, replacement) in self.replacements(line, template) {
+            self.out.write_all(&line.text[last..m.start])?;
+            if sep == ':' {
+                self.paint(|c| &c.selected_match, replacement)?;
+            } else {
+                self.paint(|c| &c.context_match, replacement)?;
             }
+            last = m.end;
+        }
+        self.out.write_all(&line.text[last..])?;
+        writeln!(self.out)
+    }
+
+    /// Prints each match of the line on its own, as -o does.
+    fn only_matching(&mut self, name: Option<&[u8]>, line: &Line) -> io::Result<()> {
+        if self.cfg.json {
+            return self.json_line(line, ':');
+        }
+        let cfg = self.cfg;
+        let matches: Vec<(usize, Cow<[u8]>)> = if cfg.multiline {
+            // each match goes whole, from the line it starts on
+            self.spans_in(line)
+                .filter(|(m, _)| m.start >= line.offset)
+                .map(|(m, replacement)| (m.start, Cow::Owned(replacement.clone())))
+                .collect()
         } else {
-            println!("{}", has.len());
+            cfg.pattern
+                .replacements(&line.text, cfg.replace.as_deref())
+                .into_iter()
+                .map(|(m, replacement)| (line.offset + m.start, replacement))
+                .collect()
+        };
+        for (offset, replacement) in matches {
+            self.prefix(name, line.number, offset, ':')?;
+            self.paint(|c| &c.selected_match, replacement)?;
+            writeln!(self.out)?;
         }
-    } else {
-        println!("{}", has.join("\n"));
+        Ok(())
     }
 
-    Ok(())
+    /// The matches in `line`, each with what to print for it, as the
+    /// pattern's `replacements` gives them. With -U they are the parts of the
+    /// file's matches that fall in the line: a match's replacement goes with
+    /// its first part and its other parts are dropped.
+    fn replacements<'l>(
+        &self,
+        line: &'l Line,
+        template: Option<&'l [u8]>,
+    ) -> Vec<(Range<usize>, Cow<'l, [u8]>)>
+    where
+        'a: 'l,
+    {
+        let cfg: &'a Config = self.cfg;
+        if !cfg.multiline {
+            return cfg.pattern.replacements(&line.text, template);
+        }
+        let end = line.offset + line.text.len();
+        self.spans_in(line)
+            .filter_map(|(m, replacement)| {
+                let part = m.start.max(line.offset) - line.offset..m.end.min(end) - line.offset;
+                match template {
+                    Some(_) if m.start >= line.offset => {
+                        Some((part, Cow::Owned(replacement.clone())))
+                    }
+                    Some(_) => Some((part, Cow::Borrowed(&b""[..]))),
+                    None if part.is_empty() => None,
+                    None => Some((part.clone(), Cow::Borrowed(&line.text[part]))),
+                }
+            })
+            .collect()
+    }
+
+    /// The -U matches that touch `line`, its newline included.
+    fn spans_in(&self, line: &Line) -> impl Iterator<Item = &(Range<usize>, Vec<u8>)> {
+        let end = line.offset + line.text.len();
+        let first = self.spans.partition_point(|(m, _)| m.end <= line.offset);
+        self.spans[first..]
+            .iter()
+            .take_while(move |(m, _)| m.start <= end)
+    }
+
+    /// Starts the --json report of a file; "begin" waits for its first line.
+    fn json_begin(&mut self, path: &[u8]) {
+        self.json = JsonFile {
+            path: path.to_vec(),
+            ..JsonFile::default()
+        };
+    }
+
+    /// A "match" event for a selected line, "context" for the others.
+    fn json_line(&mut self, line: &Line, sep: char) -> io::Result<()> {
+        if !self.json.begun {
+            self.json.begun = true;
+            self.json_event("begin", json!({ "path": json_data(&self.json.path) }))?;
+        }
+        let kind = if sep == ':' { "match" } else { "context" };
+        let submatches: Vec<Value> = self
+            .replacements(line, None)
+            .into_iter()
+            .map(|(m, text)| {
+                json!({
+                    "match": json_data(&text),
+                    "start": m.start,
+                    "end": m.end,
+                })
+            })
+            .collect();
+        if kind == "match" {
+            self.json.matched_lines += 1;
+            // with -U, a match is counted on the line it starts on
+            self.json.matches += if self.cfg.multiline {
+                self.spans_in(line)
+                    .filter(|(m, _)| m.start >= line.offset)
+                    .count()
+            } else {
+                submatches.len()
+            };
+        }
+        let mut text = line.text.clone();
+        text.push(b'\n');
+        let data = json!({
+            "path": json_data(&self.json.path),
+            "lines": json_data(&text),
+            "line_number": line.number,
+            "absolute_offset": line.offset,
+            "submatches": submatches,
+        });
+        self.json_event(kind, data)
+    }
+
+    /// The "end" event with the stats of a file that printed anything.
+    fn json_end(&mut self, bytes_searched: usize) -> io::Result<()> {
+        if !self.json.begun {
+            return Ok(());
+        }
+        let data = json!({
+            "path": json_data(&self.json.path),
+            "stats": {
+                "bytes_searched": bytes_searched,
+                "matched_lines": self.json.matched_lines,
+                "matches": self.json.matches,
+            },
+        });
+        self.json_event("end", data)
+    }
+
+    fn json_event(&mut self, kind: &str, data: Value) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, &json!({ "type": kind, "data": data }))?;
+        writeln!(self.out)
+    }
+}
+
+/// Text as JSON: a string when it is UTF-8, base64 otherwise.
+fn json_data(bytes: &[u8]) -> Value {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => json!({ "text": text }),
+        Err(_) => json!({ "bytes": BASE64.encode(bytes) }),
+    }
 }
 
-fn process_file(
-    matched: &mut Vec<String>,
-    file_path: &str,
-    pattern: &Regex,
+/// What the search of a file found.
+#[derive(Debug, Default, Clone, Copy)]
+struct Found {
+    /// selected lines
+    lines: usize,
+    /// matches in the selected lines, counted only for --count-matches; with
+    /// -v the lines hold none, so each counts once
+    matches: usize,
+}
+
+/// Streams the selected lines of `file_path` to the printer and returns what
+/// was found, or `None` if the file could not be opened, read or decompressed.
+fn process_file<W: Write>(
+    printer: &mut Printer<'_, W>,
+    file_path: &Path,
+    cfg: &Config,
     multiple: bool,
-    is_invert: bool,
-) -> MyResult<()> {
-    match open(file_path) {
-        Ok(reader) => {
-            for line in reader.lines() {
-                let line = line?;
-                if pattern.is_match(&line) ^ is_invert {
-                    let prefix = if multiple {
-                        format!("{}:", file_path)
-                    } else {
-                        "".to_string()
-                    };
-                    matched.push(format!("{}{}", prefix, line));
+) -> MyResult<Option<Found>> {
+    let (before_context, after_context) = cfg.context();
+    let with_context = before_context > 0 || after_context > 0;
+    if cfg.max_count == Some(0) {
+        return Ok(Some(Found::default()));
+    }
+
+    // where stdin starts, to hand back what -m leaves unread
+    let stdin_start = match cfg.max_count {
+        Some(_) if is_stdin(file_path) && !cfg.search_zip => stdin_position(),
+        _ => None,
+    };
+    let opened = match open(file_path) {
+        Ok(reader) if cfg.search_zip => decompress(reader),
+        opened => opened,
+    };
+    let mut reader = match opened {
+        Ok(reader) => reader,
+        Err(e) => return Ok(unreadable(file_path, cfg, e)),
+    };
+
+    // a NUL byte in the first block is what marks a file as binary
+    let binary = cfg.binary_files != BinaryFiles::Text
+        && match reader.fill_buf() {
+            Ok(block) => block.contains(&0),
+            Err(e) => return Ok(unreadable(file_path, cfg, e)),
+        };
+    if binary && cfg.binary_files == BinaryFiles::WithoutMatch {
+        return Ok(Some(Found::default()));
+    }
+
+    // -U matches against the whole file, then goes through it line by line
+    let mut matched_lines = None;
+    if cfg.multiline {
+        let mut contents = vec![];
+        if let Err(e) = reader.read_to_end(&mut contents) {
+            return Ok(unreadable(file_path, cfg, e));
+        }
+        matched_lines = Some(cfg.pattern.matched_lines(&contents));
+        printer.spans = cfg
+            .pattern
+            .replacements(&contents, cfg.replace.as_deref())
+            .into_iter()
+            .map(|(m, replacement)| (m, replacement.into_owned()))
+            .collect();
+        reader = Box::new(Cursor::new(contents));
+    }
+
+    // unprinted lines preceding the current one, at most before_context
+    let mut before: VecDeque<Line> = VecDeque::with_capacity(before_context);
+    let mut last_printed: Option<usize> = None;
+    let mut after_left = 0;
+    let mut count = 0;
+    let mut matches = 0;
+    // -m reached; only trailing context is left to print
+    let mut max_reached = false;
+
+    let display_name = display_name(file_path);
+    let name = multiple.then_some(&*display_name);
+    if cfg.json {
+        printer.json_begin(&display_name);
+    }
+
+    let mut number = 0;
+    let mut offset = 0;
+    loop {
+        if max_reached && after_left == 0 {
+            break;
+        }
+
+        // read_until keeps the terminator, so offsets stay exact with CRLF
+        let mut text = vec![];
+        let bytes = match reader.read_until(b'\n', &mut text) {
+            Ok(bytes) => bytes,
+            Err(e) => return Ok(unreadable(file_path, cfg, e)),
+        };
+        if bytes == 0 {
+            break;
+        }
+        if text.ends_with(b"\n") {
+            text.pop();
+        }
+        number += 1;
+        let line = Line {
+            number,
+            offset,
+            text,
+        };
+        offset += bytes;
+
+        if max_reached {
+            printer.line(name, &line, '-')?;
+            after_left -= 1;
+        } else if match &matched_lines {
+            Some(matched) => matched[line.number].is_some(),
+            None => cfg.pattern.is_match(&line.text),
+        } ^ cfg.invert_match
+        {
+            count += 1;
+            if cfg.count_matches {
+                matches += match &matched_lines {
+                    _ if cfg.invert_match => 1,
+                    Some(matched) => matched[line.number].unwrap_or(0),
+                    None => cfg.pattern.find_iter(&line.text).count(),
+                };
+            }
+            // one selected line is enough to decide -l, -L and -q
+            if cfg.files_with_matches || cfg.files_without_match || cfg.quiet {
+                break;
+            }
+            if cfg.max_count == Some(count) {
+                max_reached = true;
+            }
+            if cfg.count {
+                if max_reached {
+                    break;
                 }
+                continue;
+            }
+            if binary && !cfg.json {
+                printer.binary_file_matches(&display_name)?;
+                break;
+            }
+
+            let first = before.front().map_or(line.number, |l| l.number);
+            let adjacent = last_printed.is_some_and(|last| last + 1 >= first);
+            // groups from the previous file count as non-adjacent too
+            if with_context && printer.printed && !adjacent {
+                printer.separator()?;
+            }
+            for context in before.drain(..) {
+                printer.line(name, &context, '-')?;
+            }
+            if cfg.only_matching {
+                printer.only_matching(name, &line)?;
+            } else {
+                printer.line(name, &line, ':')?;
+            }
+            printer.printed = true;
+            last_printed = Some(line.number);
+            after_left = after_context;
+        } else if after_left > 0 {
+            printer.line(name, &line, '-')?;
+            last_printed = Some(line.number);
+            after_left -= 1;
+        } else if before_context > 0 {
+            if before.len() == before_context {
+                before.pop_front();
+            }
+            before.push_back(line);
+        }
+    }
+
+    // only now that nothing more is read can stdin be moved back to the end
+    // of the last line taken, the selected one or its trailing context
+    if let Some(start) = stdin_start.filter(|_| max_reached) {
+        seek_stdin(start + offset as u64)?;
+    }
+    if cfg.json {
+        printer.json_end(offset)?;
+    }
+    Ok(Some(Found {
+        lines: count,
+        matches,
+    }))
+}
+
+/// Reports why `file_path` could not be searched, unless -s, for the search to
+/// go on with the next file.
+fn unreadable(file_path: &Path, cfg: &Config, e: impl fmt::Display) -> Option<Found> {
+    if !cfg.no_messages {
+        eprintln!("{}: {}", message_name(file_path), e);
+    }
+    None
+}
+
+/// Applies --replace to the selected lines of `file_path` and writes the
+/// result back, returning how many lines were selected, or `None` if the file
+/// could not be read or rewritten.
+fn rewrite_file(file_path: &Path, cfg: &Config) -> MyResult<Option<Found>> {
+    let rewritten = if is_stdin(file_path) {
+        Err(io::Error::other("cannot rewrite in place"))
+    } else {
+        fs::read(file_path)
+    }
+    .and_then(|contents| {
+        // binary files are left alone, as they would not be printed either
+        if cfg.binary_files != BinaryFiles::Text && contents.contains(&0) {
+            return Ok(Found::default());
+        }
+        let template = cfg.replace.as_deref().unwrap_or_default();
+        if cfg.multiline {
+            let matched = cfg.pattern.matched_lines(&contents);
+            let found = Found {
+                lines: matched.iter().flatten().count(),
+                matches: matched.iter().flatten().sum(),
+            };
+            if found.lines > 0 {
+                write_atomically(file_path, &cfg.pattern.replace_all(&contents, template))?;
             }
+            return Ok(found);
+        }
+        let mut rewritten = Vec::with_capacity(contents.len());
+        let mut found = Found::default();
+        for line in contents.split_inclusive(|&b| b == b'\n') {
+            let text = line.strip_suffix(b"\n").unwrap_or(line);
+            if cfg.max_count != Some(found.lines) && cfg.pattern.is_match(text) {
+                found.lines += 1;
+                found.matches += cfg.pattern.find_iter(text).count();
+                rewritten.extend(cfg.pattern.replace_all(text, template));
+                rewritten.extend_from_slice(&line[text.len()..]);
+            } else {
+                rewritten.extend_from_slice(line);
+            }
+        }
+        if found.lines > 0 {
+            write_atomically(file_path, &rewritten)?;
         }
-        Err(e) => eprintln!("{}", e),
+        Ok(found)
+    });
+
+    match rewritten {
+        Ok(found) => Ok(Some(found)),
+        Err(e) => Ok(unreadable(file_path, cfg, e)),
+    }
+}
+
+/// Replaces the file at `path` by one holding `contents`, so that readers see
+/// either the old or the new file, never a partial one.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // the temporary file must be on the same filesystem for the rename
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+    temp.write_all(contents)?;
+    temp.as_file()
+        .set_permissions(fs::metadata(path)?.permissions())?;
+    temp.persist(path)?;
+    Ok(())
+}
+
+/// The offset of stdin, if it is a regular file.
+#[cfg(unix)]
+fn stdin_position() -> Option<u64> {
+    stdin_file()?.stream_position().ok()
+}
+
+#[cfg(not(unix))]
+fn stdin_position() -> Option<u64> {
+    None
+}
+
+/// Moves stdin to `position` once it is no longer read from, so that whatever
+/// the buffered reader read ahead is left for the next process reading stdin.
+#[cfg(unix)]
+fn seek_stdin(position: u64) -> io::Result<()> {
+    if let Some(mut stdin) = stdin_file() {
+        stdin.seek(SeekFrom::Start(position))?;
     }
+    Ok(())
+}
 
+#[cfg(not(unix))]
+fn seek_stdin(_position: u64) -> io::Result<()> {
     Ok(())
 }
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+/// A duplicate of the stdin descriptor, sharing its offset.
+#[cfg(unix)]
+fn stdin_file() -> Option<File> {
+    use std::os::fd::AsFd;
+    io::stdin()
+        .as_fd()
+        .try_clone_to_owned()
+        .ok()
+        .map(File::from)
+}
+
+/// Decompresses `reader` if it starts like a gzip, bzip2, xz or zstd stream.
+fn decompress(mut reader: Box<dyn BufRead>) -> MyResult<Box<dyn BufRead>> {
+    let magic = reader.fill_buf()?;
+    let decoder: Box<dyn Read> = if magic.starts_with(b"\x1f\x8b") {
+        Box::new(flate2::bufread::MultiGzDecoder::new(reader))
+    } else if magic.starts_with(b"BZh") {
+        Box::new(bzip2::bufread::MultiBzDecoder::new(reader))
+    } else if magic.starts_with(b"\xfd7zXZ\x00") {
+        Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader))
+    } else if magic.starts_with(b"\x28\xb5\x2f\xfd") {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else {
+        return Ok(reader);
+    };
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+fn open(filename: &Path) -> MyResult<Box<dyn BufRead>> {
+    if is_stdin(filename) {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(filename)?)))
     }
 }
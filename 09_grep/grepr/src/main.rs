@@ -1,6 +1,9 @@
 fn main() {
-    if let Err(e) = grepr::get_args().and_then(grepr::run) {
-        eprintln!("error: {}", e);
-        std::process::exit(1);
+    match grepr::get_args().and_then(grepr::run) {
+        Ok(status) => std::process::exit(status),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }
     }
 }
@@ -49,6 +49,23 @@ fn dies_bad_pattern() -> Result<()> {
     Ok(())
 }
 
+// --------------------------------------------------
+#[test]
+fn dies_pattern_only_valid_when_joined() -> Result<()> {
+    // (?:a)(b) would be valid, but a)(b is not
+    for args in [&["a)(b", FOX][..], &["-e", "fox", "-e", "(", FOX]] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .assert()
+            .code(2)
+            .stdout("")
+            .stderr(predicate::str::is_match(
+                r#"^Invalid pattern "(a\)\(b|\()": regex parse error"#,
+            )?);
+    }
+    Ok(())
+}
+
 // --------------------------------------------------
 #[test]
 fn warns_bad_file() -> Result<()> {
@@ -63,6 +80,11 @@ fn warns_bad_file() -> Result<()> {
 
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> Result<()> {
+    run_status(args, expected_file, 0)
+}
+
+// --------------------------------------------------
+fn run_status(args: &[&str], expected_file: &str, code: i32) -> Result<()> {
     let windows_file = format!("{expected_file}.windows");
     let expected_file = if os_type().unwrap() == "Windows"
         && Path::new(&windows_file).is_file()
@@ -74,7 +96,7 @@ fn run(args: &[&str], expected_file: &str) -> Result<()> {
 
     let expected = fs::read_to_string(expected_file)?;
     let output = Command::cargo_bin(PRG)?.args(args).output().expect("fail");
-    assert!(output.status.success());
+    assert_eq!(output.status.code(), Some(code));
 
     let stdout = String::from_utf8(output.stdout).expect("invalid UTF-8");
     assert_eq!(stdout, expected);
@@ -84,7 +106,7 @@ fn run(args: &[&str], expected_file: &str) -> Result<()> {
 // --------------------------------------------------
 #[test]
 fn empty_file() -> Result<()> {
-    run(&["foo", EMPTY], "tests/expected/empty.foo")
+    run_status(&["foo", EMPTY], "tests/expected/empty.foo", 1)
 }
 
 // --------------------------------------------------
@@ -120,7 +142,7 @@ fn bustle_insensitive() -> Result<()> {
 // --------------------------------------------------
 #[test]
 fn nobody() -> Result<()> {
-    run(&["nobody", NOBODY], "tests/expected/nobody.txt")
+    run_status(&["nobody", NOBODY], "tests/expected/nobody.txt", 1)
 }
 
 // --------------------------------------------------
@@ -198,7 +220,7 @@ fn insensitive_count() -> Result<()> {
 // --------------------------------------------------
 #[test]
 fn nobody_count() -> Result<()> {
-    run(&["-c", "nobody", NOBODY], "tests/expected/nobody.txt.count")
+    run_status(&["-c", "nobody", NOBODY], "tests/expected/nobody.txt.count", 1)
 }
 
 // --------------------------------------------------
@@ -285,3 +307,1359 @@ fn stdin_insensitive_count() -> Result<()> {
     assert_eq!(stdout, expected);
     Ok(())
 }
+
+// --------------------------------------------------
+#[test]
+fn bustle_after_context() -> Result<()> {
+    run(
+        &["-A", "1", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.after1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_before_context() -> Result<()> {
+    run(
+        &["--before-context", "1", "up", BUSTLE],
+        "tests/expected/bustle.txt.up.before1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_invert_context() -> Result<()> {
+    run(
+        &["-C2", "-v", "e", BUSTLE],
+        "tests/expected/bustle.txt.e.invert.context2",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_context_separator() -> Result<()> {
+    run(
+        &["-A2", "-B1", "-i", "the", BUSTLE, FOX],
+        "tests/expected/bustle.fox.the.lowercase.insensitive.after2.before1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_line_number() -> Result<()> {
+    run(
+        &["-n", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.n",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_byte_offset() -> Result<()> {
+    run(&["--byte-offset", "up", BUSTLE], "tests/expected/bustle.txt.up.b")
+}
+
+// --------------------------------------------------
+#[test]
+fn nobody_invert_line_number_byte_offset() -> Result<()> {
+    run(
+        &["-nb", "-v", "-i", "the", NOBODY],
+        "tests/expected/nobody.txt.the.invert.insensitive.n.b",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_line_number_byte_offset_context() -> Result<()> {
+    run(
+        &["-nb", "-C1", "the", BUSTLE, FOX],
+        "tests/expected/bustle.fox.the.lowercase.n.b.context1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files_with_matches() -> Result<()> {
+    run(
+        &["-l", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.l",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files_without_match() -> Result<()> {
+    run(
+        &["--files-without-match", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.L",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_files_without_match() -> Result<()> {
+    run(
+        &["-rL", "-i", "nobody", INPUTS_DIR],
+        "tests/expected/nobody.recursive.insensitive.L",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin_files_with_matches() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-l", "fox"])
+        .write_stdin(fs::read_to_string(FOX)?)
+        .assert()
+        .success()
+        .stdout("(standard input)\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_only_matching() -> Result<()> {
+    run(
+        &["-o", "-i", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.insensitive.o",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_only_matching_byte_offset() -> Result<()> {
+    run(
+        &["--only-matching", "-b", "the", BUSTLE, FOX],
+        "tests/expected/bustle.fox.the.o.b",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_always_context() -> Result<()> {
+    run(
+        &["--color=always", "-n", "-C1", "-i", "the", BUSTLE, FOX],
+        "tests/expected/bustle.fox.the.insensitive.n.context1.color",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_always_invert_context() -> Result<()> {
+    run(
+        &["--color=always", "-v", "-C1", "up", BUSTLE],
+        "tests/expected/bustle.txt.up.invert.context1.color",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_auto_not_a_terminal() -> Result<()> {
+    run(
+        &["--color", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_grep_colors() -> Result<()> {
+    let expected = fs::read_to_string("tests/expected/fox.n.color.grep_colors")?;
+    Command::cargo_bin(PRG)?
+        .args(["--color=always", "-n", "fox", FOX, BUSTLE])
+        .env("GREP_COLORS", "mt=01;32:fn=:se=33")
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_patterns() -> Result<()> {
+    run(
+        &["-e", "fox", "--regexp", "bustle", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.fox.bustle.e",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn patterns_from_stdin() -> Result<()> {
+    let expected = fs::read_to_string("tests/expected/bustle.fox.the.fox.f")?;
+    Command::cargo_bin(PRG)?
+        .args(["-f", "-", BUSTLE, FOX])
+        .write_stdin("fox\nThe\n")
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_pattern_among_many() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-e", "foo", "-e", "*foo", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(r#"Invalid pattern "*foo""#));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_strings() -> Result<()> {
+    run(&["-F", "-o", ".", FOX], "tests/expected/fox.txt.dot.F.o")
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_strings_insensitive() -> Result<()> {
+    run(
+        &["--fixed-strings", "-i", "-o", "THE", BUSTLE],
+        "tests/expected/bustle.txt.the.F.insensitive.o",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn word_regexp() -> Result<()> {
+    run(&["-w", "the", BUSTLE], "tests/expected/bustle.txt.the.w")
+}
+
+// --------------------------------------------------
+#[test]
+fn word_regexp_only_matching_insensitive() -> Result<()> {
+    run(
+        &["-wo", "-i", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.w.o.insensitive",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn word_regexp_invert_insensitive() -> Result<()> {
+    run(
+        &["--word-regexp", "-v", "-i", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.w.invert.insensitive",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn word_regexp_punctuation() -> Result<()> {
+    // only word characters next to the match count, so ! and — may end a word
+    run(
+        &["-wo", "-n", "-e", "Nobody!", "-e", "Frog—", "-e", "!", NOBODY],
+        "tests/expected/nobody.txt.punctuation.w.o.n",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn line_regexp_insensitive() -> Result<()> {
+    run(
+        &["-x", "-i", "the bustle in a house", BUSTLE],
+        "tests/expected/bustle.txt.x.insensitive",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_include() -> Result<()> {
+    run(
+        &["-r", "--include=b*", "--include=f*", "the", INPUTS_DIR],
+        "tests/expected/the.recursive.include",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_exclude() -> Result<()> {
+    run(
+        &["-r", "--exclude=b*", "the", INPUTS_DIR],
+        "tests/expected/the.recursive.exclude",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_exclude_dir() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-rl", "--exclude-dir=expected", "fox", "tests"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("tests/inputs/fox.txt"))
+        .stdout(predicate::str::contains("tests/expected").not());
+    Ok(())
+}
+
+// --------------------------------------------------
+fn make_ignore_tree() -> Result<tempfile::TempDir> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    for sub in [".git/info", "build", "sub/deep", ".hidden"] {
+        fs::create_dir_all(root.join(sub))?;
+    }
+    fs::write(root.join(".gitignore"), "build/\n*.log\n!keep.log\n")?;
+    fs::write(root.join(".git/info/exclude"), "secret.txt\n")?;
+    fs::write(root.join("sub/.ignore"), "x.txt\n")?;
+    fs::write(root.join("sub/deep/.gitignore"), "!x.txt\n")?;
+    for file in [
+        "a.txt",
+        "build/b.txt",
+        "c.log",
+        "keep.log",
+        "secret.txt",
+        "sub/x.txt",
+        "sub/y.txt",
+        "sub/deep/x.txt",
+        ".hidden/h.txt",
+    ] {
+        fs::write(root.join(file), "needle\n")?;
+    }
+    Ok(dir)
+}
+
+// --------------------------------------------------
+fn list_recursive(dir: &Path, args: &[&str]) -> Result<Vec<String>> {
+    let output = Command::cargo_bin(PRG)?
+        .current_dir(dir)
+        .args(["-rl", "needle", "."])
+        .args(args)
+        .output()
+        .expect("fail");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).expect("invalid UTF-8");
+    let mut files: Vec<String> = stdout
+        .lines()
+        .map(|line| line.replace('\\', "/"))
+        .filter(|line| !line.starts_with("./.git/"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_respects_ignore_files() -> Result<()> {
+    let dir = make_ignore_tree()?;
+    assert_eq!(
+        list_recursive(dir.path(), &[])?,
+        ["./a.txt", "./keep.log", "./sub/deep/x.txt", "./sub/y.txt"]
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_no_ignore() -> Result<()> {
+    let dir = make_ignore_tree()?;
+    assert_eq!(
+        list_recursive(dir.path(), &["--no-ignore"])?,
+        [
+            "./a.txt",
+            "./build/b.txt",
+            "./c.log",
+            "./keep.log",
+            "./secret.txt",
+            "./sub/deep/x.txt",
+            "./sub/x.txt",
+            "./sub/y.txt",
+        ]
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_hidden() -> Result<()> {
+    let dir = make_ignore_tree()?;
+    assert_eq!(
+        list_recursive(dir.path(), &["--hidden"])?,
+        [
+            "./.hidden/h.txt",
+            "./a.txt",
+            "./keep.log",
+            "./sub/deep/x.txt",
+            "./sub/y.txt",
+        ]
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_ignores_stop_at_repository() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let outer = dir.path();
+    fs::create_dir_all(outer.join("repo/.git"))?;
+    fs::create_dir_all(outer.join("repo/sub"))?;
+    fs::write(outer.join(".gitignore"), "secret.txt\n")?;
+    fs::write(outer.join("repo/.gitignore"), "*.log\n")?;
+    for file in ["repo/secret.txt", "repo/sub/secret.txt", "repo/sub/c.log"] {
+        fs::write(outer.join(file), "needle\n")?;
+    }
+
+    // rules above the repository never apply, those of the repository do
+    // from a subdirectory too
+    assert_eq!(
+        list_recursive(&outer.join("repo"), &[])?,
+        ["./secret.txt", "./sub/secret.txt"]
+    );
+    assert_eq!(list_recursive(&outer.join("repo/sub"), &[])?, ["./secret.txt"]);
+    Ok(())
+}
+
+// --------------------------------------------------
+const BINARY: &[u8] = b"hello\0world\nfoo hello\n";
+
+// --------------------------------------------------
+#[test]
+fn binary_file_matches() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .arg("hello")
+        .write_stdin(BINARY)
+        .assert()
+        .success()
+        .stdout("Binary file (standard input) matches\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_count() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "hello"])
+        .write_stdin(BINARY)
+        .assert()
+        .success()
+        .stdout("2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_files_text() -> Result<()> {
+    for args in [&["-a", "hello"][..], &["--binary-files=text", "hello"]] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .write_stdin(BINARY)
+            .assert()
+            .success()
+            .stdout(&b"hello\0world\nfoo hello\n"[..]);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_files_without_match() -> Result<()> {
+    for args in [&["-I", "-c", "hello"][..], &["--binary-files=without-match", "-c", "hello"]] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .write_stdin(BINARY)
+            .assert()
+            .code(1)
+            .stdout("0\n");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn invalid_utf8() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .arg("hello")
+        .write_stdin(&b"caf\xe9 hello\nbye\n"[..])
+        .assert()
+        .success()
+        .stdout(&b"caf\xe9 hello\n"[..]);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn sort_path() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-rl", "--sort=path", "-i", "the", INPUTS_DIR])
+        .assert()
+        .success()
+        .stdout(
+            "tests/inputs/bustle.txt\n\
+            tests/inputs/fox.txt\n\
+            tests/inputs/nobody.txt\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn threads_keep_files_together() -> Result<()> {
+    let args = ["-r", "--sort=path", "-n", "-C1", "-i", "the", INPUTS_DIR];
+    let single = Command::cargo_bin(PRG)?.arg("-j1").args(args).output()?;
+    let parallel = Command::cargo_bin(PRG)?.arg("--threads=4").args(args).output()?;
+    assert!(single.status.success());
+    assert_eq!(
+        String::from_utf8(single.stdout)?,
+        String::from_utf8(parallel.stdout)?
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn threads_keep_large_outputs_together() -> Result<()> {
+    // more output than the workers may hold back, so that files have to be
+    // printed as they are searched
+    let dir = tempfile::tempdir()?;
+    let mut files = vec![];
+    for name in ["a", "b", "c"] {
+        let path = dir.path().join(name);
+        let lines: String = (0..100_000)
+            .map(|i| format!("{name} line {i} {}\n", if i % 5 == 0 { "dog" } else { "fox" }))
+            .collect();
+        fs::write(&path, lines)?;
+        files.push(path);
+    }
+
+    let single = Command::cargo_bin(PRG)?
+        .args(["-j1", "-n", "-A1", "fox"])
+        .args(&files)
+        .output()?;
+    let parallel = Command::cargo_bin(PRG)?
+        .args(["-j3", "-n", "-A1", "fox"])
+        .args(&files)
+        .output()?;
+    assert!(single.status.success());
+    assert!(single.stdout.len() > 4 << 20);
+    assert!(single.stdout == parallel.stdout);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn threads_search_files_at_once() -> Result<()> {
+    use std::process::Stdio;
+    use std::time::{Duration, Instant};
+
+    // reading a FIFO waits for a writer, and "b" is written before "a", so
+    // grepr only finishes if it is reading both at the same time
+    let dir = tempfile::tempdir()?;
+    let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+    for fifo in [&a, &b] {
+        assert!(std::process::Command::new("mkfifo")
+            .arg(fifo)
+            .status()?
+            .success());
+    }
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(["-j2", "fox"])
+        .arg(&a)
+        .arg(&b)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let expected = format!("{}:fox a\n{}:fox b\n", a.display(), b.display());
+    std::thread::spawn(move || -> std::io::Result<()> {
+        fs::write(&b, "fox b\n")?;
+        fs::write(&a, "fox a\n")
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while child.try_wait()?.is_none() {
+        if Instant::now() > deadline {
+            child.kill()?;
+            panic!("the files were searched one after the other");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let output = child.wait_with_output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn exit_status() -> Result<()> {
+    Command::cargo_bin(PRG)?.args(["fox", FOX]).assert().code(0);
+    Command::cargo_bin(PRG)?.args(["cat", FOX]).assert().code(1);
+    Command::cargo_bin(PRG)?
+        .args(["fox", FOX, &gen_bad_file()])
+        .assert()
+        .code(2);
+    Command::cargo_bin(PRG)?
+        .args(["fox", INPUTS_DIR])
+        .assert()
+        .code(2);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-q", "fox", FOX])
+        .assert()
+        .code(0)
+        .stdout("");
+    Command::cargo_bin(PRG)?
+        .args(["--quiet", "cat", FOX])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet_match_wins_over_errors() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-q", "fox", &gen_bad_file(), FOX])
+        .assert()
+        .code(0);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_messages() -> Result<()> {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["-s", "fox", &bad, FOX])
+        .assert()
+        .code(2)
+        .stderr("")
+        .stdout(predicate::str::contains("fox.txt:The quick brown fox"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_trailing_context() -> Result<()> {
+    run(
+        &["-m2", "-A1", "-n", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.m2.after1.n",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_caps_count() -> Result<()> {
+    run(
+        &["--max-count", "2", "-c", "-i", "the", BUSTLE, NOBODY],
+        "tests/expected/bustle.nobody.the.insensitive.m2.count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_zero() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-m0", "The", BUSTLE])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn max_count_leaves_rest_of_stdin() -> Result<()> {
+    let bin = assert_cmd::cargo::cargo_bin(PRG);
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(r#""$0" -m1 The; cat"#)
+        .arg(bin)
+        .stdin(fs::File::open(BUSTLE)?)
+        .output()?;
+    assert!(output.status.success());
+
+    let bustle = fs::read_to_string(BUSTLE)?;
+    let (first, rest) = bustle.split_once('\n').unwrap();
+    let expected = format!("{first}\n{rest}");
+    assert_eq!(String::from_utf8(output.stdout)?, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn max_count_context_across_buffer_leaves_rest_of_stdin() -> Result<()> {
+    // the match ends just short of 8 KiB, so its context crosses the end of
+    // the first buffer read
+    let mut input = format!("{}\n", "x".repeat(99)).repeat(81);
+    input += &format!("MATCH{}\n", "y".repeat(84));
+    assert_eq!(input.len(), 8190);
+    for i in 0..10 {
+        input += &format!("after{i}\n");
+    }
+    let file = tempfile::NamedTempFile::new()?;
+    fs::write(file.path(), &input)?;
+
+    let bin = assert_cmd::cargo::cargo_bin(PRG);
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(r#""$0" -m1 -A3 MATCH; echo --; cat"#)
+        .arg(bin)
+        .stdin(fs::File::open(file.path())?)
+        .output()?;
+    assert!(output.status.success());
+
+    let expected = format!(
+        "MATCH{}\nafter0\nafter1\nafter2\n--\n{}",
+        "y".repeat(84),
+        (3..10).map(|i| format!("after{i}\n")).collect::<String>()
+    );
+    assert_eq!(String::from_utf8(output.stdout)?, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+fn json_events(args: &[&str]) -> Result<Vec<serde_json::Value>> {
+    let output = Command::cargo_bin(PRG)?.args(args).output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    stdout
+        .lines()
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+// --------------------------------------------------
+#[test]
+fn json_events_per_file() -> Result<()> {
+    let events = json_events(&["--json", "-i", "the", BUSTLE, EMPTY, FOX])?;
+    let kinds: Vec<_> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(
+        kinds,
+        ["begin", "match", "match", "match", "end", "begin", "match", "end"]
+    );
+    assert_eq!(events[0]["data"]["path"]["text"], BUSTLE);
+    assert_eq!(events[5]["data"]["path"]["text"], FOX);
+
+    let stats = &events[4]["data"]["stats"];
+    assert_eq!(stats["matched_lines"], 3);
+    assert_eq!(stats["matches"], 4);
+    assert_eq!(stats["bytes_searched"], fs::metadata(BUSTLE)?.len());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_match() -> Result<()> {
+    let events = json_events(&["--json", "-i", "the", BUSTLE])?;
+    let data = &events[3]["data"];
+    assert_eq!(data["line_number"], 6);
+    assert_eq!(data["absolute_offset"], 97);
+    assert_eq!(data["lines"]["text"], "The sweeping up the heart,\n");
+    assert_eq!(
+        data["submatches"],
+        serde_json::json!([
+            { "match": { "text": "The" }, "start": 0, "end": 3 },
+            { "match": { "text": "the" }, "start": 16, "end": 19 },
+        ])
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_context() -> Result<()> {
+    let events = json_events(&["--json", "-A1", "dog", FOX])?;
+    let kinds: Vec<_> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["begin", "match", "end"]);
+
+    let events = json_events(&["--json", "-B1", "-n", "sweeping", BUSTLE])?;
+    assert_eq!(events[1]["type"], "context");
+    assert_eq!(events[1]["data"]["lines"]["text"], "\n");
+    assert_eq!(events[1]["data"]["submatches"], serde_json::json!([]));
+    assert_eq!(events[2]["type"], "match");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_conflicts_with_count() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--json", "-c", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn replace_groups() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--replace", "[$1]", "-n", "(?i)(th)e", BUSTLE])
+        .assert()
+        .success()
+        .stdout(
+            "1:[Th] bustle in a house\n\
+             2:[Th] morning after death\n\
+             6:[Th] sweeping up [th] heart,\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn replace_leaves_context_lines() -> Result<()> {
+    for (args, expected) in [
+        (&["--replace", "X", "-v", "-C1", "foo"][..], "foo\nbar\nfoo\n"),
+        (&["--replace", "X", "-A1", "bar"], "X\nfoo\n"),
+        (
+            &["--replace", "X", "-v", "-A1", "--color=always", "foo"],
+            "bar\n\x1b[01;31m\x1b[Kfoo\x1b[m\x1b[K\n",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .env_remove("GREP_COLORS")
+            .write_stdin("foo\nbar\nfoo\n")
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn replace_named_only_matching() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--replace", "${color}!", r"(?P<color>\w+) fox", FOX])
+        .assert()
+        .success()
+        .stdout("brown!\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn replace_fixed() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-F", "--replace", "$1", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("The quick brown $1 jumps over the lazy dog.\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn replace_in_place() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let bustle = dir.path().join("bustle.txt");
+    let nobody = dir.path().join("nobody.txt");
+    fs::copy(BUSTLE, &bustle)?;
+    fs::copy(NOBODY, &nobody)?;
+
+    Command::cargo_bin(PRG)?
+        .args(["--in-place", "--replace", "${1}STLE", "-w", "(bu)stle"])
+        .arg(&bustle)
+        .arg(&nobody)
+        .assert()
+        .success()
+        .stdout("");
+
+    let expected = fs::read_to_string(BUSTLE)?.replace("bustle", "buSTLE");
+    assert_eq!(fs::read_to_string(&bustle)?, expected);
+    assert_eq!(fs::read_to_string(&nobody)?, fs::read_to_string(NOBODY)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place_requires_replace() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--in-place", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--replace"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place_stdin() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--in-place", "--replace", "x", "fox"])
+        .pipe_stdin(FOX)?
+        .assert()
+        .code(2)
+        .stderr("(standard input): cannot rewrite in place\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-U", "-n", r"house\nThe", BUSTLE])
+        .assert()
+        .success()
+        .stdout("1:The bustle in a house\n2:The morning after death\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_context() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--multiline", "-n", "-A1", r"industries\s+Enacted", BUSTLE])
+        .assert()
+        .success()
+        .stdout("3:Is solemnest of industries\n4:Enacted upon earth,—\n5-\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_needs_dotall_for_dot() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-U", "earth.*sweeping", BUSTLE])
+        .assert()
+        .code(1)
+        .stdout("");
+    Command::cargo_bin(PRG)?
+        .args(["-U", "--multiline-dotall", "-c", "earth.*sweeping", BUSTLE])
+        .assert()
+        .success()
+        .stdout("3\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_anchors() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-U", "-n", "^$", BUSTLE])
+        .assert()
+        .success()
+        .stdout("5:\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_no_patterns() -> Result<()> {
+    let empty = tempfile::NamedTempFile::new()?;
+    for args in [&["-U"][..], &["-U", "-c"], &["-U", "-o"], &[]] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .arg("-f")
+            .arg(empty.path())
+            .arg(BUSTLE)
+            .assert()
+            .code(1)
+            .stdout(if args.contains(&"-c") { "0\n" } else { "" });
+    }
+
+    let dir = tempfile::tempdir()?;
+    let bustle = dir.path().join("bustle.txt");
+    fs::copy(BUSTLE, &bustle)?;
+    Command::cargo_bin(PRG)?
+        .args(["-U", "--in-place", "--replace", "X", "-f"])
+        .arg(empty.path())
+        .arg(&bustle)
+        .assert()
+        .code(1);
+    assert_eq!(fs::read_to_string(&bustle)?, fs::read_to_string(BUSTLE)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_only_matching_replace_color() -> Result<()> {
+    let pattern = r"industries\s+Enacted";
+    for (args, expected) in [
+        (&["-o", "-n"][..], "3:industries\nEnacted\n"),
+        (&["-o", "-b", "--replace", "<$0>"], "62:<industries\nEnacted>\n"),
+        (&["-n", "--replace", "X"], "3:Is solemnest of X\n4: upon earth,—\n"),
+        (
+            &["--color=always"],
+            "Is solemnest of \x1b[01;31m\x1b[Kindustries\x1b[m\x1b[K\n\
+             \x1b[01;31m\x1b[KEnacted\x1b[m\x1b[K upon earth,—\n",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .arg("-U")
+            .args(args)
+            .args([pattern, BUSTLE])
+            .env_remove("GREP_COLORS")
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_json() -> Result<()> {
+    let events = json_events(&["--json", "-U", r"industries\s+Enacted", BUSTLE])?;
+    let submatches: Vec<_> = events
+        .iter()
+        .filter(|event| event["type"] == "match")
+        .map(|event| event["data"]["submatches"].clone())
+        .collect();
+    assert_eq!(
+        submatches,
+        [
+            serde_json::json!([{ "match": { "text": "industries" }, "start": 16, "end": 26 }]),
+            serde_json::json!([{ "match": { "text": "Enacted" }, "start": 0, "end": 7 }]),
+        ]
+    );
+    let stats = &events.last().unwrap()["data"]["stats"];
+    assert_eq!(stats["matched_lines"], 2);
+    assert_eq!(stats["matches"], 1);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip() -> Result<()> {
+    use std::io::Write;
+
+    let fox = fs::read(FOX)?;
+    let dir = tempfile::tempdir()?;
+    let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    gz.write_all(&fox)?;
+    fs::write(dir.path().join("1.gz"), gz.finish()?)?;
+    let mut bz = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+    bz.write_all(&fox)?;
+    fs::write(dir.path().join("2.bz2"), bz.finish()?)?;
+    let mut xz = xz2::write::XzEncoder::new(vec![], 6);
+    xz.write_all(&fox)?;
+    fs::write(dir.path().join("3.xz"), xz.finish()?)?;
+    fs::write(dir.path().join("4.zst"), zstd::encode_all(&fox[..], 0)?)?;
+    fs::copy(FOX, dir.path().join("5.txt"))?;
+
+    let output = Command::cargo_bin(PRG)?
+        .args(["-rz", "--sort=path", "-c", "fox"])
+        .arg(dir.path())
+        .output()?;
+    assert!(output.status.success());
+    let expected: String = ["1.gz", "2.bz2", "3.xz", "4.zst", "5.txt"]
+        .iter()
+        .map(|name| format!("{}:1\n", dir.path().join(name).display()))
+        .collect();
+    assert_eq!(String::from_utf8(output.stdout)?, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip_truncated() -> Result<()> {
+    use std::io::Write;
+
+    let dir = tempfile::tempdir()?;
+    let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    gz.write_all(b"ERROR c\n")?;
+    let gz = gz.finish()?;
+    fs::write(dir.path().join("a.log"), "ERROR a\n")?;
+    fs::write(dir.path().join("c.gz"), &gz[..gz.len() / 2])?;
+    fs::write(dir.path().join("d.log"), "ERROR d\n")?;
+
+    // the bad archive is reported and the files after it still searched
+    let expected: String = ["a.log", "d.log"]
+        .iter()
+        .map(|name| format!("{}:ERROR {}\n", dir.path().join(name).display(), &name[..1]))
+        .collect();
+    for threads in ["-j1", "-j4"] {
+        Command::cargo_bin(PRG)?
+            .args([threads, "-rz", "--sort=path", "ERROR"])
+            .arg(dir.path())
+            .assert()
+            .code(2)
+            .stdout(expected.clone())
+            .stderr(predicate::str::starts_with(format!(
+                "{}: ",
+                dir.path().join("c.gz").display()
+            )));
+        Command::cargo_bin(PRG)?
+            .args([threads, "-s", "-rz", "--sort=path", "ERROR"])
+            .arg(dir.path())
+            .assert()
+            .code(2)
+            .stdout(expected.clone())
+            .stderr("");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip_stdin() -> Result<()> {
+    let compressed = zstd::encode_all(fs::File::open(BUSTLE)?, 0)?;
+    Command::cargo_bin(PRG)?
+        .args(["-z", "-n", "sweeping"])
+        .write_stdin(compressed.clone())
+        .assert()
+        .success()
+        .stdout("6:The sweeping up the heart,\n");
+    Command::cargo_bin(PRG)?
+        .args(["-c", "sweeping"])
+        .write_stdin(compressed)
+        .assert()
+        .code(1)
+        .stdout("0\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn count_multiple_in_argument_order() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-j4", "-c", "The", NOBODY, FOX, EMPTY, BUSTLE])
+        .assert()
+        .success()
+        .stdout(
+            "tests/inputs/nobody.txt:1\n\
+             tests/inputs/fox.txt:1\n\
+             tests/inputs/empty.txt:0\n\
+             tests/inputs/bustle.txt:3\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn count_matches() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--count-matches", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY])
+        .assert()
+        .success()
+        .stdout(
+            "tests/inputs/bustle.txt:4\n\
+             tests/inputs/empty.txt:0\n\
+             tests/inputs/fox.txt:2\n\
+             tests/inputs/nobody.txt:4\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn count_matches_inverted() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--count-matches", "-v", "-i", "the", BUSTLE])
+        .assert()
+        .success()
+        .stdout("6\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn count_matches_multiline() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-U", "--count-matches", r"[a-z]\n\n?[A-Z]", BUSTLE])
+        .assert()
+        .success()
+        .stdout("5\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "pcre2")]
+fn perl_lookaround() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "-o", r"\w+(?= jumps)", FOX])
+        .assert()
+        .success()
+        .stdout("fox\n");
+    Command::cargo_bin(PRG)?
+        .args(["-P", "-n", r"(?<!\w)the\b", BUSTLE])
+        .assert()
+        .success()
+        .stdout("6:The sweeping up the heart,\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "pcre2")]
+fn perl_no_patterns() -> Result<()> {
+    let empty = tempfile::NamedTempFile::new()?;
+    Command::cargo_bin(PRG)?
+        .args(["-P", "-f"])
+        .arg(empty.path())
+        .arg(BUSTLE)
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "pcre2")]
+fn perl_word_regexp_punctuation() -> Result<()> {
+    run(
+        &["-P", "-wo", "-n", "-e", "Nobody!", "-e", "Frog—", "-e", "!", NOBODY],
+        "tests/expected/nobody.txt.punctuation.w.o.n",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "pcre2")]
+fn perl_backreference() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "-o", r"\b(\w)\w* \1\w*", NOBODY])
+        .assert()
+        .success()
+        .stdout("t tell\nan admiring\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "pcre2")]
+fn perl_replace() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "-i", "--replace", "<${word}>", r"(?<word>the)(?!\w)", BUSTLE])
+        .assert()
+        .success()
+        .stdout(
+            "<The> bustle in a house\n\
+             <The> morning after death\n\
+             <The> sweeping up <the> heart,\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "pcre2")]
+fn perl_invalid_pattern() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "(?<=a+)b", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::starts_with(
+            "Invalid pattern \"(?<=a+)b\": Error compiling regex",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(feature = "pcre2"))]
+fn perl_needs_feature() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("pcre2 feature"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_after_names() -> Result<()> {
+    run(
+        &["-Z", "-n", "-i", "the", BUSTLE, FOX],
+        "tests/expected/bustle.fox.the.insensitive.n.null",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn null_files_with_matches() -> Result<()> {
+    run(
+        &["--null", "-l", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.insensitive.l.null",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn null_count() -> Result<()> {
+    run(
+        &["-Zc", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.insensitive.count.null",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files_from_stdin() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--files-from=-", "-c", "-i", "the"])
+        .write_stdin(format!("{NOBODY}\n{EMPTY}\n{BUSTLE}\n"))
+        .assert()
+        .success()
+        .stdout(
+            "tests/inputs/nobody.txt:3\n\
+             tests/inputs/empty.txt:0\n\
+             tests/inputs/bustle.txt:3\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files0_from() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let odd = dir.path().join("a fox\nfile.txt");
+    fs::copy(FOX, &odd)?;
+    let list = dir.path().join("list");
+    fs::write(&list, format!("{}\0{}\0", odd.display(), BUSTLE))?;
+
+    Command::cargo_bin(PRG)?
+        .arg(format!("--files0-from={}", list.display()))
+        .args(["-Z", "-l", "fox"])
+        .assert()
+        .success()
+        .stdout(format!("{}\0", odd.display()));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(target_os = "linux")]
+fn files0_from_non_utf8_name() -> Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir()?;
+    let odd = dir.path().join(OsStr::from_bytes(b"caf\xe9.txt"));
+    fs::copy(FOX, &odd)?;
+    let list = dir.path().join("list");
+    let mut names = odd.as_os_str().as_bytes().to_vec();
+    names.extend_from_slice(format!("\0{}\0", FOX).as_bytes());
+    fs::write(&list, names)?;
+
+    // the name is printed as it is, and the next file is still searched
+    let mut expected = odd.as_os_str().as_bytes().to_vec();
+    expected.extend_from_slice(format!("\n{}\n", FOX).as_bytes());
+    Command::cargo_bin(PRG)?
+        .arg("--files0-from")
+        .arg(&list)
+        .args(["-l", "fox"])
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_from_empty_list() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--files-from", "-", "fox"])
+        .write_stdin("")
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
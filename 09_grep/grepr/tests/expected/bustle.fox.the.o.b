@@ -0,0 +1,2 @@
+tests/inputs/bustle.txt:113:the
+tests/inputs/fox.txt:31:the
@@ -0,0 +1 @@
+The sweeping up the heart,
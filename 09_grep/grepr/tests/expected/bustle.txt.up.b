@@ -0,0 +1,2 @@
+73:Enacted upon earth,—
+97:The sweeping up the heart,
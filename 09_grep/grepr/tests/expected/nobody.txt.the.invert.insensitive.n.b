@@ -0,0 +1,6 @@
+1:0:I'm Nobody! Who are you?
+2:26:Are you—Nobody—too?
+5:121:
+6:123:How dreary—to be—Somebody!
+7:155:How public—like a Frog—
+9:227:To an admiring Bog!